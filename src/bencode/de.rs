@@ -1,17 +1,60 @@
-use crate::bencode::error::Error;
+use crate::bencode::error::{Error, ErrorKind};
+use crate::bencode::spanned;
+use serde::de::value::BorrowedBytesDeserializer;
 use serde::de::{
     self, Deserialize, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
     VariantAccess, Visitor,
 };
-use std::ops::{AddAssign, MulAssign, Neg};
 
 pub struct Deserializer<'de> {
     input: &'de [u8],
+    len: usize,
+    // Keys and indices of the containers being decoded, left as they are when an
+    // error is returned so it can say where it happened
+    path: Vec<Segment<'de>>,
+    // Only canonical bencode is accepted: sorted keys without duplicates and no
+    // leading zeros
+    strict: bool,
+}
+
+enum Segment<'de> {
+    Key(&'de [u8]),
+    Index(usize),
 }
 
 impl<'de> Deserializer<'de> {
     pub fn from_bytes(input: &'de [u8]) -> Self {
-        Deserializer { input }
+        Deserializer {
+            input,
+            len: input.len(),
+            path: Vec::new(),
+            strict: false,
+        }
+    }
+
+    pub fn from_bytes_strict(input: &'de [u8]) -> Self {
+        Deserializer {
+            strict: true,
+            ..Deserializer::from_bytes(input)
+        }
+    }
+
+    fn locate(&self, err: Error) -> Error {
+        let mut path = String::new();
+
+        for segment in &self.path {
+            match segment {
+                Segment::Key(key) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(&String::from_utf8_lossy(key));
+                }
+                Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+
+        err.at(self.len - self.input.len(), path)
     }
 }
 
@@ -19,56 +62,83 @@ pub fn from_bytes<'a, T>(input: &'a [u8]) -> Result<T, Error>
 where
     T: Deserialize<'a>,
 {
-    let mut deserializer = Deserializer::from_bytes(input);
-    let t = T::deserialize(&mut deserializer)?;
+    deserialize(Deserializer::from_bytes(input))
+}
+
+// For input that has to have exactly one encoding, e.g. because it is hashed or
+// signed by others
+pub fn from_bytes_strict<'a, T>(input: &'a [u8]) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+{
+    deserialize(Deserializer::from_bytes_strict(input))
+}
+
+fn deserialize<'a, T>(mut deserializer: Deserializer<'a>) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+{
+    let t = T::deserialize(&mut deserializer).map_err(|err| deserializer.locate(err))?;
     if deserializer.input.is_empty() {
         Ok(t)
     } else {
-        Err(Error::TrailingCharacters)
+        Err(deserializer.locate(ErrorKind::TrailingCharacters.into()))
     }
 }
 
 impl<'de> Deserializer<'de> {
     fn peek_byte(&mut self) -> Result<&u8, Error> {
-        self.input.iter().next().ok_or(Error::Eof)
+        Ok(self.input.iter().next().ok_or(ErrorKind::Eof)?)
     }
 
     fn next_byte(&mut self) -> Result<&u8, Error> {
-        let byte = self.input.iter().next().ok_or(Error::Eof)?;
+        let byte = self.input.iter().next().ok_or(ErrorKind::Eof)?;
         self.input = &self.input[1..];
         Ok(byte)
     }
 
+    // Booleans are written as the integers 0 and 1
     fn parse_bool(&mut self) -> Result<bool, Error> {
-        Err(Error::Syntax)
+        let bool = match self.input {
+            [b'i', b'0', b'e', ..] => false,
+            [b'i', b'1', b'e', ..] => true,
+            _ => return Err(ErrorKind::ExpectedBoolean.into()),
+        };
+        self.input = &self.input[3..];
+        Ok(bool)
     }
 
-    fn parse_unsigned<T>(&mut self) -> Result<T, Error>
+    // Integers never have leading zeros, string lengths only in strict mode
+    fn parse_unsigned<T>(&mut self, canonical: bool) -> Result<T, Error>
     where
-        T: AddAssign<T> + MulAssign<T> + From<u8>,
+        T: TryFrom<u64>,
     {
+        if canonical
+            && self.input.starts_with(b"0")
+            && matches!(self.input.get(1), Some(b'0'..=b'9'))
+        {
+            return Err(ErrorKind::LeadingZero.into());
+        }
+
         let mut int = match self.next_byte()? {
-            byte @ b'0'..=b'9' => T::from(byte - b'0'),
-            _ => return Err(Error::ExpectedInteger),
+            byte @ b'0'..=b'9' => (byte - b'0') as u64,
+            _ => return Err(ErrorKind::ExpectedInteger.into()),
         };
 
-        loop {
-            match self.input.iter().next() {
-                Some(byte @ b'0'..=b'9') => {
-                    self.input = &self.input[1..];
-                    int *= T::from(10);
-                    int += T::from(byte - b'0');
-                }
-                _ => {
-                    return Ok(int);
-                }
-            }
+        while let Some(byte @ b'0'..=b'9') = self.input.iter().next() {
+            int = int
+                .checked_mul(10)
+                .and_then(|int| int.checked_add((byte - b'0') as u64))
+                .ok_or(ErrorKind::Overflow)?;
+            self.input = &self.input[1..];
         }
+
+        Ok(T::try_from(int).map_err(|_| ErrorKind::Overflow)?)
     }
 
     fn parse_signed<T>(&mut self) -> Result<T, Error>
     where
-        T: Neg<Output = T> + AddAssign<T> + MulAssign<T> + From<i8>,
+        T: TryFrom<i64>,
     {
         let neg = if self.peek_byte()? == &b'-' {
             let _ = self.next_byte();
@@ -80,47 +150,54 @@ impl<'de> Deserializer<'de> {
         let zero = self.peek_byte()? == &b'0';
 
         if neg && zero {
-            return Err(Error::Syntax);
+            return Err(ErrorKind::NegativeZero.into());
         }
 
         let mut int = match self.next_byte()? {
-            byte @ b'0'..=b'9' => T::from((byte - b'0') as i8),
-            _ => return Err(Error::ExpectedInteger),
+            byte @ b'0'..=b'9' => (byte - b'0') as i64,
+            _ => return Err(ErrorKind::ExpectedInteger.into()),
         };
 
-        if zero && self.peek_byte()? != &b'e' {
-            return Err(Error::Syntax);
+        if zero && matches!(self.input.first(), Some(b'0'..=b'9')) {
+            return Err(ErrorKind::LeadingZero.into());
         }
 
-        loop {
-            match self.input.iter().next() {
-                Some(byte @ b'0'..=b'9') => {
-                    self.input = &self.input[1..];
-                    int *= T::from(10);
-                    int += T::from((byte - b'0') as i8);
-                }
-                _ => {
-                    return if neg { Ok(-int) } else { Ok(int) };
-                }
-            }
+        // Negative numbers are built up below zero so that i64::MIN fits
+        if neg {
+            int = -int;
+        }
+
+        while let Some(byte @ b'0'..=b'9') = self.input.iter().next() {
+            let digit = (byte - b'0') as i64;
+            int = int
+                .checked_mul(10)
+                .and_then(|int| match neg {
+                    true => int.checked_sub(digit),
+                    false => int.checked_add(digit),
+                })
+                .ok_or(ErrorKind::Overflow)?;
+            self.input = &self.input[1..];
         }
+
+        Ok(T::try_from(int).map_err(|_| ErrorKind::Overflow)?)
     }
 
     fn parse_string(&mut self) -> Result<&'de str, Error> {
-        let len = self.parse_unsigned()?;
+        let len = self.parse_unsigned(self.strict)?;
 
-        if self.next_byte()? != &b':' {
-            return Err(Error::ExpectedString);
+        if self.peek_byte()? != &b':' {
+            return Err(ErrorKind::ExpectedString.into());
         }
+        self.next_byte()?;
 
         if self.input.len() < len {
-            return Err(Error::Eof);
+            return Err(ErrorKind::Eof.into());
         }
 
         let str = match std::str::from_utf8(&self.input[..len]) {
             Ok(str) => str,
             Err(_) => {
-                return Err(Error::ExpectedString);
+                return Err(ErrorKind::ExpectedString.into());
             }
         };
 
@@ -130,14 +207,15 @@ impl<'de> Deserializer<'de> {
     }
 
     fn parse_bytes(&mut self) -> Result<&'de [u8], Error> {
-        let len = self.parse_unsigned()?;
+        let len = self.parse_unsigned(self.strict)?;
 
-        if self.next_byte()? != &b':' {
-            return Err(Error::ExpectedString);
+        if self.peek_byte()? != &b':' {
+            return Err(ErrorKind::ExpectedString.into());
         }
+        self.next_byte()?;
 
         if self.input.len() < len {
-            return Err(Error::Eof);
+            return Err(ErrorKind::Eof.into());
         }
 
         let bytes = &self.input[..len];
@@ -160,7 +238,7 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
             b'i' => self.deserialize_i64(visitor),
             b'd' => self.deserialize_map(visitor),
             b'l' => self.deserialize_seq(visitor),
-            _ => Err(Error::Syntax),
+            _ => Err(ErrorKind::Syntax.into()),
         }
     }
 
@@ -176,14 +254,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
         let int = self.parse_signed()?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -195,14 +273,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
         let int = self.parse_signed()?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -214,14 +292,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
         let int = self.parse_signed()?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -233,14 +311,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
         let int = self.parse_signed()?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -252,14 +330,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
-        let int = self.parse_unsigned()?;
+        let int = self.parse_unsigned(true)?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -271,14 +349,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
-        let int = self.parse_unsigned()?;
+        let int = self.parse_unsigned(true)?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -290,14 +368,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
-        let int = self.parse_unsigned()?;
+        let int = self.parse_unsigned(true)?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -309,14 +387,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         V: Visitor<'de>,
     {
         if self.peek_byte()? != &b'i' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
-        let int = self.parse_unsigned()?;
+        let int = self.parse_unsigned(true)?;
 
         if self.peek_byte()? != &b'e' {
-            return Err(Error::ExpectedInteger);
+            return Err(ErrorKind::ExpectedInteger.into());
         }
         self.next_byte()?;
 
@@ -327,14 +405,14 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        Err(Error::Syntax)
+        Err(ErrorKind::Syntax.into())
     }
 
     fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Syntax)
+        Err(ErrorKind::Syntax.into())
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -343,10 +421,9 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     {
         let s = self.parse_string()?;
         if s.len() > 1 {
-            return Err(Error::Message(format!(
-                "Expected one character, got {}",
-                s.len()
-            )));
+            return Err(
+                ErrorKind::Message(format!("Expected one character, got {}", s.len())).into(),
+            );
         }
         visitor.visit_char(s.chars().next().unwrap())
     }
@@ -379,18 +456,20 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         self.deserialize_bytes(visitor)
     }
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    // Bencode has no null, a value that is present is always Some. Absent dictionary
+    // keys become None through serde's handling of missing fields.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Syntax)
+        visitor.visit_some(self)
     }
 
     fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::Syntax)
+        Err(ErrorKind::Syntax.into())
     }
 
     fn deserialize_unit_struct<V>(
@@ -401,17 +480,26 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        Err(Error::Syntax)
+        Err(ErrorKind::Syntax.into())
     }
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        if name == spanned::NAME {
+            let start = self.input;
+            return visitor.visit_seq(Span {
+                de: self,
+                start,
+                index: 0,
+            });
+        }
+
         visitor.visit_newtype_struct(self)
     }
 
@@ -419,17 +507,19 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if self.next_byte()? == &b'l' {
-            let value = visitor.visit_seq(EmptyStringSeparated::new(self))?;
+        if self.peek_byte()? != &b'l' {
+            return Err(ErrorKind::ExpectedArray.into());
+        }
+        self.next_byte()?;
 
-            if self.next_byte()? == &b'e' {
-                Ok(value)
-            } else {
-                Err(Error::ExpectedArrayEnd)
-            }
-        } else {
-            Err(Error::ExpectedArray)
+        let value = visitor.visit_seq(EmptyStringSeparated::new(self))?;
+
+        if self.peek_byte()? != &b'e' {
+            return Err(ErrorKind::ExpectedArrayEnd.into());
         }
+        self.next_byte()?;
+
+        Ok(value)
     }
 
     fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
@@ -455,17 +545,19 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     where
         V: Visitor<'de>,
     {
-        if self.next_byte()? == &b'd' {
-            let value = visitor.visit_map(EmptyStringSeparated::new(self))?;
+        if self.peek_byte()? != &b'd' {
+            return Err(ErrorKind::ExpectedMap.into());
+        }
+        self.next_byte()?;
 
-            if self.next_byte()? == &b'e' {
-                Ok(value)
-            } else {
-                Err(Error::ExpectedMapEnd)
-            }
-        } else {
-            Err(Error::ExpectedMap)
+        let value = visitor.visit_map(EmptyStringSeparated::new(self))?;
+
+        if self.peek_byte()? != &b'e' {
+            return Err(ErrorKind::ExpectedMapEnd.into());
         }
+        self.next_byte()?;
+
+        Ok(value)
     }
 
     fn deserialize_struct<V>(
@@ -499,10 +591,10 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
                 if self.next_byte()? == &b'e' {
                     Ok(value)
                 } else {
-                    Err(Error::ExpectedMapEnd)
+                    Err(ErrorKind::ExpectedMapEnd.into())
                 }
             }
-            _ => Err(Error::ExpectedEnum),
+            _ => Err(ErrorKind::ExpectedEnum.into()),
         }
     }
 
@@ -523,11 +615,17 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
 
 struct EmptyStringSeparated<'a, 'de: 'a> {
     de: &'a mut Deserializer<'de>,
+    index: usize,
+    last_key: Option<&'de [u8]>,
 }
 
 impl<'a, 'de> EmptyStringSeparated<'a, 'de> {
     fn new(de: &'a mut Deserializer<'de>) -> Self {
-        EmptyStringSeparated { de }
+        EmptyStringSeparated {
+            de,
+            index: 0,
+            last_key: None,
+        }
     }
 }
 
@@ -542,7 +640,12 @@ impl<'de> SeqAccess<'de> for EmptyStringSeparated<'_, 'de> {
             return Ok(None);
         }
 
-        seed.deserialize(&mut *self.de).map(Some)
+        self.de.path.push(Segment::Index(self.index));
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.path.pop();
+        self.index += 1;
+
+        Ok(Some(value))
     }
 }
 
@@ -557,14 +660,63 @@ impl<'de> MapAccess<'de> for EmptyStringSeparated<'_, 'de> {
             return Ok(None);
         }
 
-        seed.deserialize(&mut *self.de).map(Some)
+        // The key is taken from the input as the seed may not keep it
+        let raw = Deserializer::from_bytes(self.de.input).parse_bytes().ok();
+
+        if self.de.strict
+            && let (Some(raw), Some(last)) = (raw, self.last_key)
+        {
+            if raw == last {
+                return Err(ErrorKind::DuplicateKey.into());
+            }
+            if raw < last {
+                return Err(ErrorKind::UnsortedKeys.into());
+            }
+        }
+
+        let key = seed.deserialize(&mut *self.de)?;
+        self.last_key = raw;
+        self.de.path.push(Segment::Key(raw.unwrap_or_default()));
+
+        Ok(Some(key))
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
     where
         V: DeserializeSeed<'de>,
     {
-        seed.deserialize(&mut *self.de)
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.path.pop();
+
+        Ok(value)
+    }
+}
+
+// The value of a Spanned followed by the bytes it took up
+struct Span<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    start: &'de [u8],
+    index: usize,
+}
+
+impl<'de> SeqAccess<'de> for Span<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.index += 1;
+
+        match self.index {
+            1 => seed.deserialize(&mut *self.de).map(Some),
+            2 => {
+                let len = self.start.len() - self.de.input.len();
+                seed.deserialize(BorrowedBytesDeserializer::new(&self.start[..len]))
+                    .map(Some)
+            }
+            _ => Ok(None),
+        }
     }
 }
 
@@ -596,7 +748,7 @@ impl<'de> VariantAccess<'de> for Enum<'_, 'de> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<(), Self::Error> {
-        Err(Error::ExpectedString)
+        Err(ErrorKind::ExpectedString.into())
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
@@ -627,8 +779,11 @@ impl<'de> VariantAccess<'de> for Enum<'_, 'de> {
 
 #[cfg(test)]
 mod tests {
-    use super::Error;
-    use super::from_bytes;
+    use super::{Error, ErrorKind};
+    use super::{from_bytes, from_bytes_strict};
+    use crate::bencode::{Partial, Spanned, from_bytes_partial, to_bytes};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_zero() {
@@ -641,14 +796,208 @@ mod tests {
     fn test_negative_zero() {
         let input = b"i-0e";
 
-        assert!(matches!(from_bytes::<i64>(input), Err(Error::Syntax)));
+        assert!(matches!(
+            from_bytes::<i64>(input),
+            Err(Error {
+                kind: ErrorKind::NegativeZero,
+                ..
+            })
+        ));
     }
 
     #[test]
     fn test_leading_zero() {
         let input = b"i01e";
 
-        assert!(matches!(from_bytes::<i64>(input), Err(Error::Syntax)));
+        assert!(matches!(
+            from_bytes::<i64>(input),
+            Err(Error {
+                kind: ErrorKind::LeadingZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes::<u64>(input),
+            Err(Error {
+                kind: ErrorKind::LeadingZero,
+                ..
+            })
+        ));
+        assert_eq!(from_bytes::<i64>(b"i0e").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_prefix() {
+        let input = b"i42eabc";
+
+        assert_eq!(
+            from_bytes_partial::<i64>(input).unwrap(),
+            Partial::Complete(42, 4)
+        );
+        assert!(matches!(
+            from_bytes::<i64>(input),
+            Err(Error {
+                kind: ErrorKind::TrailingCharacters,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_error_location() {
+        #[derive(Debug, Deserialize)]
+        struct File {
+            _length: i64,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Info {
+            _files: Vec<File>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Torrent {
+            _info: Info,
+        }
+
+        let input = b"d5:_infod6:_filesld7:_lengthi1eed7:_length1:xeeee";
+        let err = from_bytes::<Torrent>(input).unwrap_err();
+
+        assert!(matches!(err.kind, ErrorKind::ExpectedInteger));
+        assert_eq!(err.offset, Some(42));
+        assert_eq!(err.path, "_info._files[1]._length");
+        assert_eq!(
+            err.to_string(),
+            "Expected integer: _info._files[1]._length at byte 42"
+        );
+        assert_eq!(
+            err.snippet(input).unwrap(),
+            format!(
+                "    {}\n    {}^",
+                "filesld7:_lengthi1eed7:_length1:xeeee",
+                " ".repeat(30)
+            )
+        );
+
+        let err = from_bytes::<i64>(b"i1ex").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected trailing characters at byte 3");
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(
+            from_bytes::<i64>(b"i-9223372036854775808e").unwrap(),
+            i64::MIN
+        );
+        assert_eq!(
+            from_bytes::<u64>(b"i18446744073709551615e").unwrap(),
+            u64::MAX
+        );
+        assert!(matches!(
+            from_bytes::<i64>(b"i9223372036854775808e"),
+            Err(Error {
+                kind: ErrorKind::Overflow,
+                offset: Some(19),
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes::<u8>(b"i256e"),
+            Err(Error {
+                kind: ErrorKind::Overflow,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes::<&[u8]>(b"99999999999999999999:"),
+            Err(Error {
+                kind: ErrorKind::Overflow,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_strict() {
+        let canonical = b"d1:ai1e1:bi2ee";
+
+        assert_eq!(
+            from_bytes_strict::<BTreeMap<String, i64>>(canonical).unwrap()["b"],
+            2
+        );
+
+        let unsorted = b"d1:bi2e1:ai1ee";
+        assert!(from_bytes::<BTreeMap<String, i64>>(unsorted).is_ok());
+        assert!(matches!(
+            from_bytes_strict::<BTreeMap<String, i64>>(unsorted),
+            Err(Error {
+                kind: ErrorKind::UnsortedKeys,
+                offset: Some(7),
+                ..
+            })
+        ));
+
+        let duplicate = b"d1:ai1e1:ai2ee";
+        assert!(from_bytes::<BTreeMap<String, i64>>(duplicate).is_ok());
+        assert!(matches!(
+            from_bytes_strict::<BTreeMap<String, i64>>(duplicate),
+            Err(Error {
+                kind: ErrorKind::DuplicateKey,
+                ..
+            })
+        ));
+
+        let leading_zero = b"03:abc";
+        assert_eq!(from_bytes::<String>(leading_zero).unwrap(), "abc");
+        assert!(matches!(
+            from_bytes_strict::<String>(leading_zero),
+            Err(Error {
+                kind: ErrorKind::LeadingZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes_strict::<u64>(b"i007e"),
+            Err(Error {
+                kind: ErrorKind::LeadingZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes_strict::<i64>(b"i03e"),
+            Err(Error {
+                kind: ErrorKind::LeadingZero,
+                ..
+            })
+        ));
+        assert!(matches!(
+            from_bytes_strict::<i64>(b"i-0e"),
+            Err(Error {
+                kind: ErrorKind::NegativeZero,
+                ..
+            })
+        ));
+
+        // Keys of nested dictionaries are compared on their own
+        assert!(
+            from_bytes_strict::<BTreeMap<String, BTreeMap<String, i64>>>(
+                b"d1:bd1:ai1ee1:cd1:ai1eee"
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_bool() {
+        assert!(from_bytes::<bool>(b"i1e").unwrap());
+        assert!(!from_bytes::<bool>(b"i0e").unwrap());
+        assert!(matches!(
+            from_bytes::<bool>(b"i2e"),
+            Err(Error {
+                kind: ErrorKind::ExpectedBoolean,
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -687,4 +1036,45 @@ mod tests {
             (String::from("a"), 'b', 1i64)
         );
     }
+
+    #[test]
+    fn test_option() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Test {
+            a: Option<i64>,
+            b: Option<String>,
+        }
+
+        assert_eq!(from_bytes::<Option<i64>>(b"i1e").unwrap(), Some(1));
+        assert_eq!(
+            from_bytes::<Test>(b"d1:b1:x1:ci1ee").unwrap(),
+            Test {
+                a: None,
+                b: Some(String::from("x"))
+            }
+        );
+        assert_eq!(
+            from_bytes::<Test>(b"de").unwrap(),
+            Test { a: None, b: None }
+        );
+    }
+
+    #[test]
+    fn test_spanned() {
+        #[derive(Deserialize)]
+        struct Test<'a> {
+            #[serde(borrow)]
+            a: Spanned<'a, BTreeMap<String, i64>>,
+            b: i64,
+        }
+
+        // The keys of `a` are not sorted, its bytes are kept as they are anyway
+        let input = b"d1:ad1:bi2e1:ai1ee1:bi3ee";
+        let test = from_bytes::<Test>(input).unwrap();
+
+        assert_eq!(test.a.bytes(), b"d1:bi2e1:ai1ee");
+        assert_eq!(test.a["a"], 1);
+        assert_eq!(test.b, 3);
+        assert_eq!(to_bytes(&test.a).unwrap(), test.a.bytes());
+    }
 }
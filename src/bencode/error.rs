@@ -1,9 +1,22 @@
 use serde::{de, ser};
 
 use std::fmt::{self, Display};
+use std::io;
+
+// Bytes shown on each side of the error in a snippet
+const SNIPPET_CONTEXT: usize = 30;
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    // Where decoding stopped, None for errors while serializing
+    pub offset: Option<usize>,
+    // Dictionary keys and list indices leading to the value, e.g. info.files[3].length
+    pub path: String,
+}
 
 #[derive(Debug)]
-pub enum Error {
+pub enum ErrorKind {
     Message(String),
     Eof,
     Syntax,
@@ -16,35 +29,124 @@ pub enum Error {
     ExpectedMapEnd,
     ExpectedEnum,
     TrailingCharacters,
+    // Outside of strict mode only for a Value or when serializing
+    DuplicateKey,
+    // In a string length only in strict mode
+    LeadingZero,
+    NegativeZero,
+    // Only in strict mode
+    UnsortedKeys,
+    // An integer or length that does not fit its type
+    Overflow,
+    // Longer than a stream decoder buffers
+    TooLarge,
+    // The writer failed while serializing
+    Io(io::Error),
+}
+
+impl Error {
+    // Sets the location unless the error already has one from deeper down
+    pub fn at(mut self, offset: usize, path: String) -> Self {
+        if self.offset.is_none() {
+            self.offset = Some(offset);
+            self.path = path;
+        }
+        self
+    }
+
+    // The input around the error with a caret under the offending byte. Bytes that
+    // are not printable ASCII are shown as dots to keep the caret in place.
+    pub fn snippet(&self, input: &[u8]) -> Option<String> {
+        let offset = self.offset?.min(input.len());
+        let start = offset.saturating_sub(SNIPPET_CONTEXT);
+        let end = (offset + SNIPPET_CONTEXT).min(input.len());
+
+        let line: String = input[start..end]
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+
+        Some(format!("    {}\n    {:>2$}", line, "^", offset - start + 1))
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            offset: None,
+            path: String::new(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        ErrorKind::Io(err).into()
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err.kind {
+            ErrorKind::Io(err) => err,
+            _ => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
 }
 
 impl de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
+    }
+
+    // The path of the error already names the field
+    fn duplicate_field(_field: &'static str) -> Self {
+        ErrorKind::DuplicateKey.into()
     }
 }
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        ErrorKind::Message(msg.to_string()).into()
     }
 }
 
 impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.offset, self.path.as_str()) {
+            (Some(offset), "") => write!(f, "{} at byte {}", self.kind, offset),
+            (Some(offset), path) => write!(f, "{}: {} at byte {}", self.kind, path, offset),
+            (None, _) => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Message(msg) => write!(f, "{}", msg),
-            Error::Eof => f.write_str("Unexpected end of input"),
-            Error::Syntax => f.write_str("Invalid syntax"),
-            Error::ExpectedBoolean => f.write_str("Expected boolean"),
-            Error::ExpectedInteger => f.write_str("Expected integer"),
-            Error::ExpectedString => f.write_str("Expected string"),
-            Error::ExpectedArray => f.write_str("Expected array"),
-            Error::ExpectedArrayEnd => f.write_str("Expected end of array"),
-            Error::ExpectedMap => f.write_str("Expected map"),
-            Error::ExpectedMapEnd => f.write_str("Expected end of map"),
-            Error::ExpectedEnum => f.write_str("Expected enum"),
-            Error::TrailingCharacters => f.write_str("Unexpected trailing characters"),
+            ErrorKind::Message(msg) => write!(f, "{}", msg),
+            ErrorKind::Eof => f.write_str("Unexpected end of input"),
+            ErrorKind::Syntax => f.write_str("Invalid syntax"),
+            ErrorKind::ExpectedBoolean => f.write_str("Expected boolean"),
+            ErrorKind::ExpectedInteger => f.write_str("Expected integer"),
+            ErrorKind::ExpectedString => f.write_str("Expected string"),
+            ErrorKind::ExpectedArray => f.write_str("Expected array"),
+            ErrorKind::ExpectedArrayEnd => f.write_str("Expected end of array"),
+            ErrorKind::ExpectedMap => f.write_str("Expected map"),
+            ErrorKind::ExpectedMapEnd => f.write_str("Expected end of map"),
+            ErrorKind::ExpectedEnum => f.write_str("Expected enum"),
+            ErrorKind::TrailingCharacters => f.write_str("Unexpected trailing characters"),
+            ErrorKind::DuplicateKey => f.write_str("Duplicate dictionary key"),
+            ErrorKind::LeadingZero => f.write_str("Leading zero"),
+            ErrorKind::NegativeZero => f.write_str("Negative zero"),
+            ErrorKind::UnsortedKeys => f.write_str("Dictionary keys are not sorted"),
+            ErrorKind::Overflow => f.write_str("Integer overflow"),
+            ErrorKind::TooLarge => f.write_str("Value too large"),
+            ErrorKind::Io(err) => write!(f, "{}", err),
         }
     }
 }
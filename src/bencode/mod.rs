@@ -1,7 +1,13 @@
 mod de;
 mod error;
 mod ser;
+mod spanned;
+mod stream;
+mod value;
 
-pub use crate::bencode::de::from_bytes;
+pub use crate::bencode::de::{from_bytes, from_bytes_strict};
 pub use crate::bencode::error::Error;
-pub use crate::bencode::ser::to_bytes;
+pub use crate::bencode::ser::{serialized_size, to_bytes, to_writer, to_writer_async};
+pub use crate::bencode::spanned::Spanned;
+pub use crate::bencode::stream::{Decoder, Partial, from_bytes_partial};
+pub use crate::bencode::value::{Value, from_value, to_value};
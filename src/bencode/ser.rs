@@ -1,20 +1,296 @@
-use crate::bencode::error::Error;
-use serde::ser::{self, Serialize};
-
-pub struct Serializer {
+use crate::bencode::error::{Error, ErrorKind};
+use crate::bencode::spanned;
+use serde::ser::{self, Error as _, Serialize};
+use std::io;
+use std::ops::Range;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+// Writes canonical bencode: dictionary entries are sorted by their raw keys. The
+// entries of a dictionary are written as they come and only moved around when it
+// ends with its keys out of order. A dictionary's last key may belong first, so
+// nothing is passed on to the writer while one is open.
+pub struct Serializer<W> {
+    // Dictionaries being written, the innermost last
+    dicts: Vec<Dict>,
+    // Entries of the open dictionaries
+    entries: Vec<Entry>,
+    // Set while writing a dictionary value, the only place a None can be left out
+    optional: bool,
+    // Set while writing a dictionary key
+    key: bool,
+    // Set while writing the original bytes of a Spanned
+    raw: bool,
+    // Where the last string was written, for the key being written
+    string: Range<usize>,
+    // Only the length is wanted, nothing is kept
+    counting: bool,
+    count: usize,
     output: Vec<u8>,
+    writer: Option<W>,
+}
+
+struct Dict {
+    // Where its entries start in `output` and in `entries`
+    start: usize,
+    entries: usize,
+    sorted: bool,
+    // The entry being written and its raw key
+    entry_start: usize,
+    value_start: usize,
+    key: Range<usize>,
+}
+
+// Positions in `output` of an entry and of its raw key
+struct Entry {
+    range: Range<usize>,
+    key: Range<usize>,
 }
 
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, Error>
 where
-    T: Serialize,
+    T: ?Sized + Serialize,
 {
-    let mut serializer = Serializer { output: Vec::new() };
+    let mut serializer = Serializer::<io::Sink>::new(None, false);
     value.serialize(&mut serializer)?;
     Ok(serializer.output)
 }
 
-impl ser::Serializer for &mut Serializer {
+// Passes the encoded value on to the writer as it goes, apart from dictionaries
+// which are held until they end
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<(), Error>
+where
+    W: io::Write,
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::new(Some(writer), false);
+    value.serialize(&mut serializer)
+}
+
+pub async fn to_writer_async<W, T>(writer: &mut W, value: &T) -> Result<(), Error>
+where
+    W: AsyncWrite + Unpin,
+    T: ?Sized + Serialize,
+{
+    writer.write_all(&to_bytes(value)?).await?;
+    Ok(())
+}
+
+// Length of the encoded value. The order of dictionary entries doesn't change it,
+// so nothing is kept and duplicate keys aren't noticed.
+pub fn serialized_size<T>(value: &T) -> Result<usize, Error>
+where
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::<io::Sink>::new(None, true);
+    value.serialize(&mut serializer)?;
+    Ok(serializer.count)
+}
+
+impl<W: io::Write> Serializer<W> {
+    fn new(writer: Option<W>, counting: bool) -> Self {
+        Self {
+            dicts: Vec::new(),
+            entries: Vec::new(),
+            optional: false,
+            key: false,
+            raw: false,
+            string: 0..0,
+            counting,
+            count: 0,
+            output: Vec::new(),
+            writer,
+        }
+    }
+
+    fn position(&self) -> usize {
+        if self.counting {
+            self.count
+        } else {
+            self.output.len()
+        }
+    }
+
+    fn truncate(&mut self, position: usize) {
+        if self.counting {
+            self.count = position;
+        } else {
+            self.output.truncate(position);
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.counting {
+            self.count += bytes.len();
+            return Ok(());
+        }
+
+        self.output.extend_from_slice(bytes);
+        if let (true, Some(writer)) = (self.dicts.is_empty(), &mut self.writer) {
+            writer.write_all(&self.output)?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+
+    fn check_key(&self) -> Result<(), Error> {
+        if self.key {
+            return Err(Error::custom("Dictionary keys must be strings"));
+        }
+        Ok(())
+    }
+
+    // Integers are written without going through a string
+    fn write_int(&mut self, negative: bool, mut v: u64) -> Result<(), Error> {
+        let mut digits = [0; 21];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (v % 10) as u8;
+            v /= 10;
+            if v == 0 {
+                break;
+            }
+        }
+        if negative {
+            start -= 1;
+            digits[start] = b'-';
+        }
+        self.write(&digits[start..])
+    }
+
+    fn write_string(&mut self, v: &[u8]) -> Result<(), Error> {
+        // A Spanned is written as the bytes it was decoded from
+        if self.raw {
+            self.check_key()?;
+            return self.write(v);
+        }
+
+        self.write_int(false, v.len() as u64)?;
+        self.write(":".as_bytes())?;
+        let start = self.position();
+        self.write(v)?;
+        self.string = start..self.position();
+        Ok(())
+    }
+
+    fn begin_dict(&mut self) -> Result<(), Error> {
+        self.check_key()?;
+        self.write("d".as_bytes())?;
+
+        self.dicts.push(Dict {
+            start: self.position(),
+            entries: self.entries.len(),
+            sorted: true,
+            entry_start: 0,
+            value_start: 0,
+            key: 0..0,
+        });
+        Ok(())
+    }
+
+    fn end_dict(&mut self) -> Result<(), Error> {
+        let Some(dict) = self.dicts.pop() else {
+            return Err(Error::custom("No dictionary to end"));
+        };
+
+        if !dict.sorted {
+            let output = &self.output;
+            let entries = &mut self.entries[dict.entries..];
+            entries.sort_unstable_by(|a, b| output[a.key.clone()].cmp(&output[b.key.clone()]));
+            if entries
+                .windows(2)
+                .any(|pair| output[pair[0].key.clone()] == output[pair[1].key.clone()])
+            {
+                return Err(ErrorKind::DuplicateKey.into());
+            }
+
+            let sorted: Vec<u8> = entries
+                .iter()
+                .flat_map(|entry| &output[entry.range.clone()])
+                .copied()
+                .collect();
+            self.output[dict.start..].copy_from_slice(&sorted);
+        }
+
+        self.entries.truncate(dict.entries);
+        self.write("e".as_bytes())
+    }
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let entry_start = self.position();
+        self.optional = false;
+        self.key = true;
+        let result = key.serialize(&mut *self);
+        self.key = false;
+        result?;
+
+        let value_start = self.position();
+        let Some(dict) = self.dicts.last_mut() else {
+            return Err(Error::custom("Key outside of a dictionary"));
+        };
+        dict.entry_start = entry_start;
+        dict.value_start = value_start;
+        dict.key = self.string.clone();
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.optional = true;
+        value.serialize(&mut *self)?;
+        self.optional = false;
+        self.end_entry()
+    }
+
+    fn end_entry(&mut self) -> Result<(), Error> {
+        let position = self.position();
+        let Some(dict) = self.dicts.last_mut() else {
+            return Err(Error::custom("Value outside of a dictionary"));
+        };
+
+        // Every other value takes up at least one byte, so an empty one is a None
+        // and its key is left out as well
+        if position == dict.value_start {
+            let start = dict.entry_start;
+            self.truncate(start);
+            return Ok(());
+        }
+
+        if self.counting {
+            return Ok(());
+        }
+
+        let key = dict.key.clone();
+        if let Some(last) = self.entries[dict.entries..].last() {
+            let (last, key) = (&self.output[last.key.clone()], &self.output[key.clone()]);
+            if last == key {
+                return Err(ErrorKind::DuplicateKey.into());
+            }
+            dict.sorted &= last < key;
+        }
+
+        self.entries.push(Entry {
+            range: dict.entry_start..position,
+            key,
+        });
+        Ok(())
+    }
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.optional = false;
+        value.serialize(self)
+    }
+}
+
+impl<W: io::Write> ser::Serializer for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
     type SerializeSeq = Self;
@@ -25,13 +301,9 @@ impl ser::Serializer for &mut Serializer {
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
+    // Bencode has no booleans, they are written as the integers 0 and 1
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(if v {
-            "true".as_bytes()
-        } else {
-            "false".as_bytes()
-        });
-        Ok(())
+        self.serialize_i64(i64::from(v))
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
@@ -47,10 +319,10 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("i".as_bytes());
-        self.output.extend_from_slice(&v.to_string().into_bytes());
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.check_key()?;
+        self.write("i".as_bytes())?;
+        self.write_int(v < 0, v.unsigned_abs())?;
+        self.write("e".as_bytes())
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
@@ -66,39 +338,40 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.serialize_i64(v as i64)
+        self.check_key()?;
+        self.write("i".as_bytes())?;
+        self.write_int(false, v)?;
+        self.write("e".as_bytes())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
         self.serialize_f64(f64::from(v))
     }
 
-    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.serialize_i64(v as i64)
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Err(Error::custom("Floats cannot be encoded"))
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        self.serialize_str(&v.to_string())
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        self.output
-            .extend_from_slice(&v.len().to_string().into_bytes());
-        self.output.extend_from_slice(":".as_bytes());
-        self.output.extend_from_slice(v.as_bytes());
-        Ok(())
+        self.write_string(v.as_bytes())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.output
-            .extend_from_slice(&v.len().to_string().into_bytes());
-        self.output.extend_from_slice(":".as_bytes());
-        self.output.extend_from_slice(v);
-        Ok(())
+        self.write_string(v)
     }
 
+    // None is written as nothing at all and dictionaries leave out its key as well,
+    // anywhere else it would be lost
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        self.serialize_unit()
+        if self.optional {
+            Ok(())
+        } else {
+            Err(Error::custom("None is only allowed as a dictionary value"))
+        }
     }
 
     fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
@@ -127,12 +400,21 @@ impl ser::Serializer for &mut Serializer {
 
     fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: ?Sized + Serialize,
     {
+        // A Spanned passes its original bytes as a string, they are written without
+        // the length prefix
+        if name == spanned::NAME {
+            self.raw = true;
+            let result = value.serialize(&mut *self);
+            self.raw = false;
+            return result;
+        }
+
         value.serialize(self)
     }
 
@@ -146,15 +428,15 @@ impl ser::Serializer for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.output.extend_from_slice("d".as_bytes());
-        variant.serialize(&mut *self)?;
-        value.serialize(&mut *self)?;
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.begin_dict()?;
+        self.serialize_key(variant)?;
+        self.serialize_value(value)?;
+        self.end_dict()
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        self.output.extend_from_slice("l".as_bytes());
+        self.check_key()?;
+        self.write("l".as_bytes())?;
         Ok(self)
     }
 
@@ -177,14 +459,14 @@ impl ser::Serializer for &mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.output.extend_from_slice("d".as_bytes());
-        variant.serialize(&mut *self)?;
-        self.output.extend_from_slice("l".as_bytes());
+        self.begin_dict()?;
+        self.serialize_key(variant)?;
+        self.write("l".as_bytes())?;
         Ok(self)
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        self.output.extend_from_slice("d".as_bytes());
+        self.begin_dict()?;
         Ok(self)
     }
 
@@ -203,14 +485,14 @@ impl ser::Serializer for &mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.output.extend_from_slice("d".as_bytes());
-        variant.serialize(&mut *self)?;
-        self.output.extend_from_slice("d".as_bytes());
+        self.begin_dict()?;
+        self.serialize_key(variant)?;
+        self.begin_dict()?;
         Ok(self)
     }
 }
 
-impl ser::SerializeSeq for &mut Serializer {
+impl<W: io::Write> ser::SerializeSeq for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -218,16 +500,15 @@ impl ser::SerializeSeq for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.write("e".as_bytes())
     }
 }
 
-impl ser::SerializeTuple for &mut Serializer {
+impl<W: io::Write> ser::SerializeTuple for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -235,16 +516,15 @@ impl ser::SerializeTuple for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.write("e".as_bytes())
     }
 }
 
-impl ser::SerializeTupleStruct for &mut Serializer {
+impl<W: io::Write> ser::SerializeTupleStruct for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -252,16 +532,15 @@ impl ser::SerializeTupleStruct for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.write("e".as_bytes())
     }
 }
 
-impl ser::SerializeTupleVariant for &mut Serializer {
+impl<W: io::Write> ser::SerializeTupleVariant for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -269,16 +548,17 @@ impl ser::SerializeTupleVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("ee".as_bytes());
-        Ok(())
+        self.write("e".as_bytes())?;
+        self.end_entry()?;
+        self.end_dict()
     }
 }
 
-impl ser::SerializeMap for &mut Serializer {
+impl<W: io::Write> ser::SerializeMap for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -286,23 +566,22 @@ impl ser::SerializeMap for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)
+        Serializer::serialize_key(self, key)
     }
 
     fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        Serializer::serialize_value(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.end_dict()
     }
 }
 
-impl ser::SerializeStruct for &mut Serializer {
+impl<W: io::Write> ser::SerializeStruct for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -310,17 +589,15 @@ impl ser::SerializeStruct for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)
+        ser::SerializeMap::serialize_entry(self, key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("e".as_bytes());
-        Ok(())
+        self.end_dict()
     }
 }
 
-impl ser::SerializeStructVariant for &mut Serializer {
+impl<W: io::Write> ser::SerializeStructVariant for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
@@ -328,19 +605,22 @@ impl ser::SerializeStructVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)
+        ser::SerializeMap::serialize_entry(self, key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice("ee".as_bytes());
-        Ok(())
+        self.end_dict()?;
+        self.end_entry()?;
+        self.end_dict()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::to_bytes;
+    use super::{serialized_size, to_bytes, to_writer, to_writer_async};
+    use crate::bencode::error::ErrorKind;
+    use serde::{Serialize, Serializer};
+    use std::collections::{BTreeMap, HashMap};
 
     #[test]
     fn test_str() {
@@ -369,4 +649,162 @@ mod tests {
 
         assert_eq!(to_bytes(&tuple).unwrap(), b"l1:a1:bi1ee");
     }
+
+    #[test]
+    fn test_option() {
+        #[derive(Serialize)]
+        struct Test {
+            a: Option<i64>,
+            b: Option<String>,
+        }
+
+        let test = Test {
+            a: None,
+            b: Some(String::from("x")),
+        };
+        let map = BTreeMap::from([("a", Some(1)), ("b", None), ("c", Some(3))]);
+
+        assert_eq!(to_bytes(&test).unwrap(), b"d1:b1:xe");
+        assert_eq!(to_bytes(&map).unwrap(), b"d1:ai1e1:ci3ee");
+        assert_eq!(to_bytes(&Some(1)).unwrap(), b"i1e");
+
+        // There is nothing to write a None as outside of a dictionary
+        assert!(to_bytes(&None::<i64>).is_err());
+        assert!(to_bytes(&[Some(1), None]).is_err());
+    }
+
+    #[test]
+    fn test_sorted_keys() {
+        #[derive(Serialize)]
+        struct Test {
+            b: i64,
+            #[serde(rename = "a b")]
+            a: HashMap<String, i64>,
+        }
+
+        let test = Test {
+            b: 1,
+            a: HashMap::from([(String::from("z"), 1), (String::from("y"), 2)]),
+        };
+
+        assert_eq!(to_bytes(&test).unwrap(), b"d3:a bd1:yi2e1:zi1ee1:bi1ee");
+
+        let outer = BTreeMap::from([("a", Some(test)), ("c", None)]);
+        assert_eq!(
+            to_bytes(&outer).unwrap(),
+            b"d1:ad3:a bd1:yi2e1:zi1ee1:bi1eee"
+        );
+
+        let optional = HashMap::from([("b", None), ("c", Some(1)), ("a", Some(2))]);
+        assert_eq!(to_bytes(&optional).unwrap(), b"d1:ai2e1:ci1ee");
+
+        #[derive(Serialize)]
+        enum Variant {
+            Tuple(i64, i64),
+            Struct { b: i64, a: i64 },
+        }
+
+        let variants = (Variant::Tuple(1, 2), Variant::Struct { b: 1, a: 2 });
+        assert_eq!(
+            to_bytes(&variants).unwrap(),
+            b"ld5:Tupleli1ei2eeed6:Structd1:ai2e1:bi1eeee"
+        );
+    }
+
+    struct Duplicates;
+
+    impl Serialize for Duplicates {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            serializer.collect_map([("b", 1), ("a", 2), ("b", 3)])
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        assert!(matches!(
+            to_bytes(&Duplicates).unwrap_err().kind,
+            ErrorKind::DuplicateKey
+        ));
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(to_bytes(&u64::MAX).unwrap(), b"i18446744073709551615e");
+        assert_eq!(to_bytes(&i64::MIN).unwrap(), b"i-9223372036854775808e");
+        assert_eq!(to_bytes(&true).unwrap(), b"i1e");
+        assert_eq!(to_bytes(&false).unwrap(), b"i0e");
+        assert!(to_bytes(&1.5).is_err());
+    }
+
+    #[derive(Serialize)]
+    struct Nested {
+        z: Vec<BTreeMap<String, Option<i64>>>,
+        a: Option<String>,
+        m: HashMap<String, (i64, String)>,
+    }
+
+    fn nested() -> Nested {
+        Nested {
+            z: vec![BTreeMap::from([
+                (String::from("x"), None),
+                (String::from("y"), Some(-5)),
+            ])],
+            a: None,
+            m: HashMap::from([
+                (String::from("q"), (1, String::from("one"))),
+                (String::from("p"), (22, String::from("two"))),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_to_writer() {
+        let mut output = Vec::new();
+        to_writer(&mut output, &nested()).unwrap();
+
+        assert_eq!(output, b"d1:md1:pli22e3:twoe1:qli1e3:oneee1:zld1:yi-5eeee");
+
+        let mut small = [0; 4];
+        let err = to_writer(&mut small[..], &nested()).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Io(_)));
+
+        // Values outside of dictionaries are written as they come
+        let mut small = [0; 8];
+        let err = to_writer(&mut small[..], &vec![1; 100]).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Io(_)));
+        assert_eq!(&small, b"li1ei1ei");
+    }
+
+    #[tokio::test]
+    async fn test_to_writer_async() {
+        let mut output = Vec::new();
+        to_writer_async(&mut output, &("a", 1)).await.unwrap();
+
+        assert_eq!(output, b"l1:ai1ee");
+
+        let large = BTreeMap::from([("a", vec![u64::MAX; 10000])]);
+        let mut output = Vec::new();
+        to_writer_async(&mut output, &large).await.unwrap();
+
+        assert_eq!(output, to_bytes(&large).unwrap());
+    }
+
+    #[test]
+    fn test_serialized_size() {
+        assert_eq!(
+            serialized_size(&nested()).unwrap(),
+            to_bytes(&nested()).unwrap().len()
+        );
+        assert_eq!(serialized_size(&[1, 2]).unwrap(), 8);
+        assert_eq!(
+            serialized_size(&BTreeMap::from([("a", None::<i64>)])).unwrap(),
+            2
+        );
+        assert!(serialized_size(&BTreeMap::from([(1, 2)])).is_err());
+        assert!(serialized_size(&[None::<i64>]).is_err());
+        assert_eq!(serialized_size(&Duplicates).unwrap(), 20);
+    }
 }
@@ -0,0 +1,80 @@
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+// Newtype struct name the bencode deserializer and serializer recognize. The
+// deserializer hands out the value followed by the bytes it was parsed from, the
+// serializer writes those bytes back as they are.
+pub const NAME: &str = "$bencode::Spanned";
+
+// A value together with the exact bytes it was decoded from, e.g. to hash the info
+// dictionary of a torrent as it is in the file
+#[derive(Debug)]
+pub struct Spanned<'a, T> {
+    bytes: &'a [u8],
+    value: T,
+}
+
+impl<'a, T> Spanned<'a, T> {
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<T> Deref for Spanned<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<'de: 'a, 'a, T> Deserialize<'de> for Spanned<'a, T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(NAME, SpannedVisitor(PhantomData))
+    }
+}
+
+impl<T> Serialize for Spanned<'_, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(NAME, serde_bytes::Bytes::new(self.bytes))
+    }
+}
+
+struct SpannedVisitor<'a, T>(PhantomData<Spanned<'a, T>>);
+
+impl<'de: 'a, 'a, T> Visitor<'de> for SpannedVisitor<'a, T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = Spanned<'a, T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bencoded value with its bytes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let value = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let bytes: &'de [u8] = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+        Ok(Spanned { bytes, value })
+    }
+}
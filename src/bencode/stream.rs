@@ -0,0 +1,339 @@
+use crate::bencode::de::from_bytes;
+use crate::bencode::error::{Error, ErrorKind};
+use serde::Deserialize;
+use std::io::{self, Read};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const READ_SIZE: usize = 4096;
+// Largest value a decoder buffers, well above any torrent or metadata
+const MAX_VALUE_SIZE: usize = 1 << 26;
+// Longest integer or string length a scanner waits for, enough for any i64
+const MAX_NUMBER_LENGTH: usize = 20;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Partial<T> {
+    // The value and the number of bytes it took up
+    Complete(T, usize),
+    // The input ends before the value does
+    Incomplete,
+}
+
+// Deserializes a value at the start of `input` that may be cut short or followed by
+// other data, e.g. the dictionary in front of a ut_metadata piece
+pub fn from_bytes_partial<'a, T>(input: &'a [u8]) -> Result<Partial<T>, Error>
+where
+    T: Deserialize<'a>,
+{
+    match Scanner::new(usize::MAX).scan(input)? {
+        Some(len) => Ok(Partial::Complete(from_bytes(&input[..len])?, len)),
+        None => Ok(Partial::Incomplete),
+    }
+}
+
+// Finds where the first value of a growing buffer ends without decoding it. Every
+// call continues from the last complete token, so each byte is looked at about once.
+#[derive(Debug)]
+struct Scanner {
+    depth: usize,
+    // Longest value accepted, a string is checked against it as soon as its length
+    // is known
+    limit: usize,
+    position: usize,
+}
+
+impl Scanner {
+    fn new(limit: usize) -> Self {
+        Self {
+            depth: 0,
+            limit,
+            position: 0,
+        }
+    }
+
+    fn scan(&mut self, input: &[u8]) -> Result<Option<usize>, Error> {
+        self.tokens(input)
+            .map_err(|kind| Error::from(kind).at(self.position, String::new()))
+    }
+
+    fn tokens(&mut self, input: &[u8]) -> Result<Option<usize>, ErrorKind> {
+        while let Some(&byte) = input.get(self.position) {
+            match byte {
+                b'd' | b'l' => {
+                    self.depth += 1;
+                    self.position += 1;
+                }
+                b'e' if self.depth > 0 => {
+                    self.depth -= 1;
+                    self.position += 1;
+                }
+                b'i' => {
+                    let Some(len) = number(&input[self.position + 1..], b'e')? else {
+                        return Ok(None);
+                    };
+                    self.position += len + 2;
+                }
+                b'0'..=b'9' => {
+                    let Some(len) = number(&input[self.position..], b':')? else {
+                        return Ok(None);
+                    };
+                    let length: usize = std::str::from_utf8(&input[self.position..][..len])
+                        .ok()
+                        .and_then(|length| length.parse().ok())
+                        .ok_or(ErrorKind::Syntax)?;
+                    let end = (self.position + len + 1)
+                        .checked_add(length)
+                        .ok_or(ErrorKind::Syntax)?;
+
+                    if end > self.limit {
+                        return Err(ErrorKind::TooLarge);
+                    }
+                    if input.len() < end {
+                        return Ok(None);
+                    }
+                    self.position = end;
+                }
+                _ => return Err(ErrorKind::Syntax),
+            }
+
+            if self.depth == 0 {
+                return Ok(Some(self.position));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Length of the number at the start of `input` that ends with `terminator`, None
+// if the input ends first
+fn number(input: &[u8], terminator: u8) -> Result<Option<usize>, ErrorKind> {
+    for (index, byte) in input.iter().enumerate().take(MAX_NUMBER_LENGTH + 1) {
+        match byte {
+            b'0'..=b'9' => {}
+            b'-' if index == 0 && terminator == b'e' => {}
+            _ if *byte == terminator && index > 0 => return Ok(Some(index)),
+            _ => return Err(ErrorKind::Syntax),
+        }
+    }
+
+    if input.len() > MAX_NUMBER_LENGTH {
+        return Err(ErrorKind::Syntax);
+    }
+
+    Ok(None)
+}
+
+// Splits a byte stream into bencoded values. Only as much as the reader returns is
+// buffered, the values are handed out as slices of that buffer.
+#[derive(Debug)]
+pub struct Decoder<R> {
+    buffer: Vec<u8>,
+    // Bytes of the last value handed out, removed on the next call
+    consumed: usize,
+    limit: usize,
+    reader: R,
+    scanner: Scanner,
+}
+
+impl<R> Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            buffer: Vec::new(),
+            consumed: 0,
+            limit: MAX_VALUE_SIZE,
+            reader,
+            scanner: Scanner::new(MAX_VALUE_SIZE),
+        }
+    }
+
+    fn advance(&mut self) {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+        self.scanner = Scanner::new(self.limit);
+    }
+
+    fn scan(&mut self) -> io::Result<Option<usize>> {
+        let end = self.scanner.scan(&self.buffer).map_err(invalid_data)?;
+
+        if end.is_none() && self.buffer.len() > self.limit {
+            return Err(invalid_data(ErrorKind::TooLarge.into()));
+        }
+
+        self.consumed = end.unwrap_or_default();
+        Ok(end)
+    }
+
+    // The stream may only end between values
+    fn end(&self) -> io::Result<Option<&[u8]>> {
+        if self.buffer.is_empty() {
+            Ok(None)
+        } else {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    // Bytes of the next value, None at the end of the stream
+    pub fn next(&mut self) -> io::Result<Option<&[u8]>>
+    where
+        R: Read,
+    {
+        self.advance();
+
+        loop {
+            if let Some(end) = self.scan()? {
+                return Ok(Some(&self.buffer[..end]));
+            }
+
+            let mut chunk = [0; READ_SIZE];
+            let len = self.reader.read(&mut chunk)?;
+
+            if len == 0 {
+                return self.end();
+            }
+
+            self.buffer.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    pub async fn next_async(&mut self) -> io::Result<Option<&[u8]>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.advance();
+
+        loop {
+            if let Some(end) = self.scan()? {
+                return Ok(Some(&self.buffer[..end]));
+            }
+
+            self.buffer.reserve(READ_SIZE);
+
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return self.end();
+            }
+        }
+    }
+}
+
+fn invalid_data(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Partial, Scanner, from_bytes_partial};
+    use crate::bencode::Value;
+    use std::io::{self, Read};
+
+    #[test]
+    fn test_partial() {
+        let input = b"d1:ai-12e1:bl3:xyzee";
+
+        for len in 0..input.len() {
+            assert_eq!(
+                from_bytes_partial::<Value>(&input[..len]).unwrap(),
+                Partial::Incomplete
+            );
+        }
+
+        let mut with_data = input.to_vec();
+        with_data.extend_from_slice(b"data");
+
+        let Partial::Complete(value, len) = from_bytes_partial::<Value>(&with_data).unwrap() else {
+            panic!("incomplete");
+        };
+        assert_eq!(len, input.len());
+        assert_eq!(
+            value.path("b[0]").and_then(Value::as_bytes),
+            Some(&b"xyz"[..])
+        );
+
+        assert!(from_bytes_partial::<Value>(b"x").is_err());
+        assert!(from_bytes_partial::<Value>(b"e").is_err());
+        assert!(from_bytes_partial::<Value>(b"99999999999999999999999:").is_err());
+        assert!(from_bytes_partial::<Value>(b"i1x").is_err());
+    }
+
+    #[test]
+    fn test_scanner_resumes() {
+        let input = b"l4:spami42ee";
+        let mut scanner = Scanner::new(usize::MAX);
+
+        for len in 0..input.len() {
+            assert_eq!(scanner.scan(&input[..len]).unwrap(), None);
+        }
+        assert_eq!(scanner.scan(input).unwrap(), Some(input.len()));
+    }
+
+    // Hands out one byte per read
+    struct Trickle<'a>(&'a [u8]);
+
+    impl io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.0.len().min(buf.len()).min(1);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_decoder() {
+        let mut decoder = Decoder::new(Trickle(b"i1ed1:a0:e3:abc"));
+
+        assert_eq!(decoder.next().unwrap(), Some(&b"i1e"[..]));
+        assert_eq!(decoder.next().unwrap(), Some(&b"d1:a0:e"[..]));
+        assert_eq!(decoder.next().unwrap(), Some(&b"3:abc"[..]));
+        assert_eq!(decoder.next().unwrap(), None);
+
+        let mut decoder = Decoder::new(&b"li1e"[..]);
+        assert_eq!(
+            decoder.next().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_decoder_limit() {
+        let limit = |decoder: &mut Decoder<_>| {
+            decoder.limit = 1 << 16;
+            decoder.scanner = Scanner::new(decoder.limit);
+        };
+
+        // The declared length is enough, the string isn't read
+        let mut decoder = Decoder::new(b"9999999999:".chain(io::repeat(b'a').take(1 << 20)));
+        limit(&mut decoder);
+        assert_eq!(
+            decoder.next().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(decoder.reader.get_ref().1.limit(), 1 << 20);
+
+        // Values without a length are stopped once the buffer is full
+        let mut decoder = Decoder::new(b"l".chain(io::repeat(b'l').take(1 << 20)));
+        limit(&mut decoder);
+        assert_eq!(
+            decoder.next().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(decoder.reader.get_ref().1.limit() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_decoder_async() {
+        let (mut writer, reader) = tokio::io::duplex(4);
+        let mut decoder = Decoder::new(reader);
+
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            writer.write_all(b"d3:key5:valuee2:xy").await.unwrap();
+        });
+
+        assert_eq!(
+            decoder.next_async().await.unwrap(),
+            Some(&b"d3:key5:valuee"[..])
+        );
+        assert_eq!(decoder.next_async().await.unwrap(), Some(&b"2:xy"[..]));
+        assert_eq!(decoder.next_async().await.unwrap(), None);
+    }
+}
@@ -0,0 +1,1009 @@
+use crate::bencode::de::from_bytes;
+use crate::bencode::error::{Error, ErrorKind};
+use crate::bencode::spanned;
+use serde::de::value::{BorrowedBytesDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+
+const INDENT: usize = 2;
+
+// Any bencoded value. Strings borrow from the input when decoded with from_bytes,
+// dictionaries are ordered by their raw keys like in canonical bencode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Int(i64),
+    Bytes(Cow<'a, [u8]>),
+    List(Vec<Value<'a>>),
+    Dict(BTreeMap<Cow<'a, [u8]>, Value<'a>>),
+}
+
+impl<'a> Value<'a> {
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    // Value of `key` if this is a dictionary
+    pub fn get(&self, key: &str) -> Option<&Value<'a>> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    // Looks up a nested value with a path like `info.files[3].length`, an empty
+    // path is the value itself
+    pub fn path(&self, path: &str) -> Option<&Value<'a>> {
+        let mut value = self;
+
+        for segment in path.split('.') {
+            let key_end = segment.find('[').unwrap_or(segment.len());
+            let key = &segment[..key_end];
+
+            if !key.is_empty() {
+                value = value.get(key)?;
+            }
+
+            for index in segment[key_end..].split('[').skip(1) {
+                let index: usize = index.strip_suffix(']')?.parse().ok()?;
+
+                value = match value {
+                    Value::List(list) => list.get(index)?,
+                    _ => return None,
+                };
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Int(int) => Value::Int(int),
+            Value::Bytes(bytes) => Value::Bytes(Cow::Owned(bytes.into_owned())),
+            Value::List(list) => Value::List(list.into_iter().map(Value::into_owned).collect()),
+            Value::Dict(dict) => Value::Dict(
+                dict.into_iter()
+                    .map(|(key, value)| (Cow::Owned(key.into_owned()), value.into_owned()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn write_indented(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        match self {
+            Value::Int(int) => write!(f, "{}", int),
+            Value::Bytes(bytes) => write_bytes(f, bytes),
+            Value::List(list) if list.is_empty() => f.write_str("[]"),
+            Value::List(list) => {
+                f.write_str("[\n")?;
+
+                for (index, value) in list.iter().enumerate() {
+                    write!(f, "{:1$}", "", indent + INDENT)?;
+                    value.write_indented(f, indent + INDENT)?;
+                    f.write_str(if index + 1 < list.len() { ",\n" } else { "\n" })?;
+                }
+
+                write!(f, "{:1$}]", "", indent)
+            }
+            Value::Dict(dict) if dict.is_empty() => f.write_str("{}"),
+            Value::Dict(dict) => {
+                f.write_str("{\n")?;
+
+                for (index, (key, value)) in dict.iter().enumerate() {
+                    write!(f, "{:1$}", "", indent + INDENT)?;
+                    write_bytes(f, key)?;
+                    f.write_str(": ")?;
+                    value.write_indented(f, indent + INDENT)?;
+                    f.write_str(if index + 1 < dict.len() { ",\n" } else { "\n" })?;
+                }
+
+                write!(f, "{:1$}}}", "", indent)
+            }
+        }
+    }
+}
+
+// Strings are quoted, those that are not UTF-8 (e.g. hashes) have every byte
+// outside printable ASCII escaped as \xNN
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    f.write_char('"')?;
+
+    match std::str::from_utf8(bytes) {
+        Ok(str) => write!(f, "{}", str.escape_debug())?,
+        Err(_) => {
+            for byte in bytes {
+                match byte {
+                    b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                    0x20..=0x7e => f.write_char(*byte as char)?,
+                    _ => write!(f, "\\x{:02x}", byte)?,
+                }
+            }
+        }
+    }
+
+    f.write_char('"')
+}
+
+impl Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+impl Serialize for Value<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Int(int) => serializer.serialize_i64(*int),
+            Value::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Value::List(list) => serializer.collect_seq(list),
+            Value::Dict(dict) => serializer.collect_map(
+                dict.iter()
+                    .map(|(key, value)| (serde_bytes::Bytes::new(key), value)),
+            ),
+        }
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Value<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a bencoded value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Value::Int(v as i64))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        i64::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+        Ok(Value::Bytes(Cow::Borrowed(v.as_bytes())))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Value::Bytes(Cow::Owned(v.as_bytes().to_vec())))
+    }
+
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(Value::Bytes(Cow::Borrowed(v)))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Value::Bytes(Cow::Owned(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Value::Bytes(Cow::Owned(v)))
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut list = Vec::new();
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut dict = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+            let Value::Bytes(key) = key else {
+                return Err(de::Error::custom("Dictionary keys must be strings"));
+            };
+            // Keeping either value would hide that the input is malformed
+            if dict.insert(key, value).is_some() {
+                return Err(de::Error::duplicate_field("dictionary key"));
+            }
+        }
+        Ok(Value::Dict(dict))
+    }
+}
+
+// Converts a Value into any type that can be decoded from bencode, strings may
+// borrow from the value
+pub fn from_value<'de, T>(value: &'de Value) -> Result<T, Error>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(value)
+}
+
+// Reads a Value the way the bencode deserializer reads the bytes it stands for
+impl<'de> Deserializer<'de> for &'de Value<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Int(int) => visitor.visit_i64(*int),
+            Value::Bytes(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Value::List(list) => {
+                let mut seq = SeqDeserializer::new(list.iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Dict(dict) => {
+                let mut map = MapDeserializer::new(
+                    dict.iter()
+                        .map(|(key, value)| (BorrowedBytesDeserializer::new(&key[..]), value)),
+                );
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Int(0) => visitor.visit_bool(false),
+            Value::Int(1) => visitor.visit_bool(true),
+            _ => Err(ErrorKind::ExpectedBoolean.into()),
+        }
+    }
+
+    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(ErrorKind::Syntax.into())
+    }
+
+    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(ErrorKind::Syntax.into())
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(as_str(self)?)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(ErrorKind::Syntax.into())
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(ErrorKind::Syntax.into())
+    }
+
+    // The bytes a Spanned was decoded from are gone once they are a Value
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if name == spanned::NAME {
+            return Err(de::Error::custom("Spanned values need the encoded bytes"));
+        }
+
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Bytes(_) => visitor.visit_enum(as_str(self)?.into_deserializer()),
+            Value::Dict(dict) if dict.len() == 1 => visitor.visit_enum(Enum(dict)),
+            _ => Err(ErrorKind::ExpectedEnum.into()),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char bytes byte_buf seq tuple
+        tuple_struct map struct ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Value<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+fn as_str<'de>(value: &'de Value) -> Result<&'de str, Error> {
+    value
+        .as_bytes()
+        .and_then(|bytes| std::str::from_utf8(bytes).ok())
+        .ok_or_else(|| ErrorKind::ExpectedString.into())
+}
+
+// An enum variant with data, a dictionary with the variant name as its only key
+struct Enum<'de, 'a>(&'de BTreeMap<Cow<'a, [u8]>, Value<'a>>);
+
+impl<'de> EnumAccess<'de> for Enum<'de, '_> {
+    type Error = Error;
+    type Variant = &'de Value<'de>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let Some((variant, value)) = self.0.iter().next() else {
+            return Err(ErrorKind::ExpectedEnum.into());
+        };
+        let variant = seed.deserialize(BorrowedBytesDeserializer::<Error>::new(&variant[..]))?;
+        Ok((variant, value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for &'de Value<'de> {
+    type Error = Error;
+
+    // Unit variants are plain strings, never dictionaries
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Err(ErrorKind::ExpectedString.into())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+}
+
+// Builds the Value of anything that can be encoded, with the same rules as the
+// serializer
+pub fn to_value<T>(value: &T) -> Result<Value<'static>, Error>
+where
+    T: ?Sized + Serialize,
+{
+    value.serialize(ValueSerializer)?.ok_or_else(none_error)
+}
+
+fn none_error() -> Error {
+    ser::Error::custom("None is only allowed as a dictionary value")
+}
+
+// Variants with data are dictionaries with the variant name as their only key
+fn variant(name: &'static str, value: Value<'static>) -> Value<'static> {
+    Value::Dict(BTreeMap::from([(Cow::Borrowed(name.as_bytes()), value)]))
+}
+
+// Serializes into a Value, None is only kept as Ok(None) so a dictionary can leave
+// out its key
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeDict;
+    type SerializeStruct = SerializeDict;
+    type SerializeStructVariant = SerializeDict;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(Value::Int(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    // A Value only holds what decodes as an i64
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        let v = i64::try_from(v).map_err(|_| Error::from(ErrorKind::Overflow))?;
+        self.serialize_i64(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Err(ser::Error::custom("Floats cannot be encoded"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(Value::Bytes(Cow::Owned(v.to_vec()))))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str("")
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        // A Spanned passes its original bytes, which are decoded into the Value
+        if name == spanned::NAME
+            && let Some(Value::Bytes(raw)) = value.serialize(ValueSerializer)?
+        {
+            return Ok(Some(from_bytes::<Value>(&raw)?.into_owned()));
+        }
+
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        // Like any dictionary value a None leaves out its key
+        Ok(Some(match value.serialize(ValueSerializer)? {
+            Some(value) => variant(variant_name, value),
+            None => Value::Dict(BTreeMap::new()),
+        }))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SerializeList {
+            variant: None,
+            list: Vec::with_capacity(len.unwrap_or_default()),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeList {
+            variant: Some(variant),
+            list: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SerializeDict {
+            variant: None,
+            dict: BTreeMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeDict {
+            variant: Some(variant),
+            dict: BTreeMap::new(),
+            key: None,
+        })
+    }
+}
+
+struct SerializeList {
+    // Set for a tuple variant, which is wrapped in a dictionary
+    variant: Option<&'static str>,
+    list: Vec<Value<'static>>,
+}
+
+impl SerializeList {
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.list.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<Value<'static>>, Error> {
+        let list = Value::List(self.list);
+        Ok(Some(match self.variant {
+            Some(name) => variant(name, list),
+            None => list,
+        }))
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeList::end(self)
+    }
+}
+
+struct SerializeDict {
+    // Set for a struct variant, which is wrapped in another dictionary
+    variant: Option<&'static str>,
+    dict: BTreeMap<Cow<'static, [u8]>, Value<'static>>,
+    // The key of the value that comes next
+    key: Option<Cow<'static, [u8]>>,
+}
+
+impl SerializeDict {
+    fn end(self) -> Result<Option<Value<'static>>, Error> {
+        let dict = Value::Dict(self.dict);
+        Ok(Some(match self.variant {
+            Some(name) => variant(name, dict),
+            None => dict,
+        }))
+    }
+}
+
+impl ser::SerializeMap for SerializeDict {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        match key.serialize(ValueSerializer)? {
+            Some(Value::Bytes(key)) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(ser::Error::custom("Dictionary keys must be strings")),
+        }
+    }
+
+    // A None value leaves out its key
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let Some(key) = self.key.take() else {
+            return Err(ser::Error::custom("Value without a key"));
+        };
+
+        if let Some(value) = value.serialize(ValueSerializer)?
+            && self.dict.insert(key, value).is_some()
+        {
+            return Err(ErrorKind::DuplicateKey.into());
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeDict::end(self)
+    }
+}
+
+impl ser::SerializeStruct for SerializeDict {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeDict::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeDict {
+    type Ok = Option<Value<'static>>;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeMap::serialize_entry(self, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        SerializeDict::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, from_value, to_value};
+    use crate::bencode::error::ErrorKind;
+    use crate::bencode::{Spanned, from_bytes, to_bytes};
+    use serde::{Deserialize, Serialize};
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+
+    const TORRENT: &[u8] = b"d8:announce9:http://a/4:infod5:filesld6:lengthi3e4:pathl1:aeed6:lengthi7e4:pathl1:b1:ceee4:name3:dir6:pieces2:\xff\x01ee";
+
+    #[test]
+    fn test_borrowed() {
+        let value = from_bytes::<Value>(TORRENT).unwrap();
+
+        assert!(matches!(
+            value.get("announce"),
+            Some(Value::Bytes(Cow::Borrowed(b"http://a/")))
+        ));
+        assert_eq!(to_bytes(&value).unwrap(), TORRENT);
+    }
+
+    #[test]
+    fn test_path() {
+        let value = from_bytes::<Value>(TORRENT).unwrap();
+
+        assert_eq!(value.path("info.files[1].length"), Some(&Value::Int(7)));
+        assert_eq!(
+            value
+                .path("info.files[1].path[1]")
+                .and_then(Value::as_bytes),
+            Some(&b"c"[..])
+        );
+        assert_eq!(value.path(""), Some(&value));
+        assert_eq!(value.path("info.files[2]"), None);
+        assert_eq!(value.path("info.name[0]"), None);
+        assert_eq!(value.path("missing"), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let value = from_bytes::<Value>(b"d1:ai-1e1:bl2:\"x2:\xff\x00lee1:cdee").unwrap();
+
+        assert_eq!(
+            value.to_string(),
+            "{\n  \"a\": -1,\n  \"b\": [\n    \"\\\"x\",\n    \"\\xff\\x00\",\n    []\n  ],\n  \"c\": {}\n}"
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        let map = BTreeMap::from([(String::from("b"), 2), (String::from("a"), 1)]);
+        let value = to_value(&map).unwrap();
+
+        assert_eq!(value.get("a"), Some(&Value::Int(1)));
+        assert_eq!(from_value::<BTreeMap<String, i64>>(&value).unwrap(), map);
+        assert!(from_value::<Vec<i64>>(&value).is_err());
+    }
+
+    #[test]
+    fn test_from_value_borrowed() {
+        #[derive(Debug, Deserialize)]
+        struct Torrent<'a> {
+            announce: &'a str,
+            info: Info<'a>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Info<'a> {
+            #[serde(borrow)]
+            files: Vec<File<'a>>,
+            name: &'a str,
+            #[serde(with = "serde_bytes")]
+            pieces: &'a [u8],
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct File<'a> {
+            length: u64,
+            #[serde(borrow)]
+            path: Vec<&'a str>,
+        }
+
+        let value = from_bytes::<Value>(TORRENT).unwrap();
+        let torrent = from_value::<Torrent>(&value).unwrap();
+
+        assert_eq!(torrent.announce, "http://a/");
+        assert_eq!(torrent.info.name, "dir");
+        assert_eq!(torrent.info.pieces, b"\xff\x01");
+        assert_eq!(torrent.info.files[1].length, 7);
+        assert_eq!(torrent.info.files[1].path, ["b", "c"]);
+        assert!(from_value::<&str>(&Value::Int(1)).is_err());
+    }
+
+    #[test]
+    fn test_to_value() {
+        #[derive(Serialize)]
+        enum Kind {
+            Unit,
+            Newtype(i64),
+            Tuple(i64, &'static str),
+            Struct { a: Option<i64> },
+        }
+
+        #[derive(Serialize)]
+        struct Test<'a> {
+            z: Vec<Kind>,
+            a: Option<String>,
+            #[serde(with = "serde_bytes")]
+            m: &'a [u8],
+            info: Spanned<'a, Value<'a>>,
+        }
+
+        let info = from_bytes::<Spanned<Value>>(b"d1:bi1e1:ai2ee").unwrap();
+        let test = Test {
+            z: vec![
+                Kind::Unit,
+                Kind::Newtype(1),
+                Kind::Tuple(2, "x"),
+                Kind::Struct { a: None },
+            ],
+            a: None,
+            m: b"\xff",
+            info,
+        };
+
+        let value = to_value(&test).unwrap();
+        assert_eq!(
+            value,
+            from_bytes::<Value>(&to_bytes(&test).unwrap()).unwrap()
+        );
+        assert_eq!(value.path("info.b"), Some(&Value::Int(1)));
+        assert_eq!(value.get("a"), None);
+
+        assert!(matches!(
+            to_value(&u64::MAX).unwrap_err().kind,
+            ErrorKind::Overflow
+        ));
+        assert!(to_value(&None::<i64>).is_err());
+        assert!(to_value(&[Some(1), None]).is_err());
+        assert!(to_value(&BTreeMap::from([(1, 2)])).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let err = from_bytes::<Value>(b"d1:ai1e1:bi2e1:ai3ee").unwrap_err();
+
+        assert!(matches!(err.kind, ErrorKind::DuplicateKey));
+        assert!(err.offset.is_some());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::bencode::{self, Error};
+use crate::dht::routing::{ID_LENGTH, Node, NodeId};
+use crate::tracker::{COMPACT_V4_LENGTH, COMPACT_V6_LENGTH, Peer, compact};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::net::SocketAddr;
+
+pub const ERROR_GENERIC: i64 = 201;
+pub const ERROR_PROTOCOL: i64 = 203;
+pub const ERROR_METHOD_UNKNOWN: i64 = 204;
+
+// Arguments of a query (`a`)
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Arguments {
+    #[serde(default, with = "serde_bytes")]
+    pub id: Vec<u8>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub implied_port: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub info_hash: Vec<u8>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub port: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub target: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub token: Vec<u8>,
+}
+
+// Return values of a response (`r`)
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Values {
+    #[serde(default, with = "serde_bytes")]
+    pub id: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub nodes: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub nodes6: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub token: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<ByteBuf>,
+}
+
+impl Values {
+    pub fn set_nodes(&mut self, nodes: &[Node]) {
+        (self.nodes, self.nodes6) = compact_nodes(nodes);
+    }
+
+    pub fn nodes(&self) -> Vec<(NodeId, SocketAddr)> {
+        parse_nodes(&self.nodes, &self.nodes6)
+    }
+
+    pub fn peers(&self) -> Vec<Peer> {
+        self.values
+            .iter()
+            .filter_map(|value| Peer::from_compact(value))
+            .collect()
+    }
+}
+
+// Compact node info, the ID followed by the compact address, of the IPv4 and
+// the IPv6 nodes
+pub fn compact_nodes(nodes: &[Node]) -> (Vec<u8>, Vec<u8>) {
+    let mut v4 = Vec::new();
+    let mut v6 = Vec::new();
+
+    for node in nodes {
+        let compact_nodes = match node.addr {
+            SocketAddr::V4(_) => &mut v4,
+            SocketAddr::V6(_) => &mut v6,
+        };
+        compact_nodes.extend_from_slice(&node.id);
+        compact_nodes.extend_from_slice(&compact(&node.addr));
+    }
+
+    (v4, v6)
+}
+
+pub fn parse_nodes(v4: &[u8], v6: &[u8]) -> Vec<(NodeId, SocketAddr)> {
+    let v4 = v4.chunks_exact(ID_LENGTH + COMPACT_V4_LENGTH);
+    let v6 = v6.chunks_exact(ID_LENGTH + COMPACT_V6_LENGTH);
+
+    v4.chain(v6)
+        .filter_map(|node| {
+            let (id, addr) = node.split_at(ID_LENGTH);
+            Some((id.try_into().ok()?, Peer::from_compact(addr)?.addr))
+        })
+        .collect()
+}
+
+// A KRPC message: a query, a response or an error depending on `y`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(default, skip_serializing_if = "Arguments::is_empty")]
+    pub a: Arguments,
+    #[serde(default, skip_serializing_if = "is_no_error")]
+    pub e: (i64, String),
+    // Address the message was sent to, in responses (BEP 42)
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub ip: Vec<u8>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub q: String,
+    #[serde(default, skip_serializing_if = "Values::is_empty")]
+    pub r: Values,
+    #[serde(with = "serde_bytes")]
+    pub t: Vec<u8>,
+    pub y: String,
+}
+
+impl Message {
+    pub fn query(t: Vec<u8>, method: &str, arguments: Arguments) -> Self {
+        Self {
+            a: arguments,
+            q: method.to_string(),
+            t,
+            y: String::from("q"),
+            ..Self::default()
+        }
+    }
+
+    pub fn response(t: Vec<u8>, addr: SocketAddr, values: Values) -> Self {
+        Self {
+            ip: compact(&addr),
+            r: values,
+            t,
+            y: String::from("r"),
+            ..Self::default()
+        }
+    }
+
+    // Address the sender of a response saw us at
+    pub fn ip(&self) -> Option<SocketAddr> {
+        Peer::from_compact(&self.ip).map(|peer| peer.addr)
+    }
+
+    pub fn error(t: Vec<u8>, code: i64, message: &str) -> Self {
+        Self {
+            e: (code, message.to_string()),
+            t,
+            y: String::from("e"),
+            ..Self::default()
+        }
+    }
+
+    // Messages from other nodes have to be canonical bencode
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        bencode::from_bytes_strict(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bencode::to_bytes(self)
+    }
+}
+
+impl Arguments {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Values {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn is_no_error(e: &(i64, String)) -> bool {
+    e.0 == 0
+}
+
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arguments, Message, Values};
+    use crate::dht::routing::Node;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_query() {
+        let message = Message::query(
+            b"aa".to_vec(),
+            "ping",
+            Arguments {
+                id: b"abcdefghij0123456789".to_vec(),
+                ..Arguments::default()
+            },
+        );
+        let bytes = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+        assert_eq!(Message::from_bytes(bytes).unwrap(), message);
+    }
+
+    #[test]
+    fn test_announce_peer() {
+        let bytes = b"d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe";
+        let message = Message::from_bytes(bytes).unwrap();
+
+        assert_eq!(message.q, "announce_peer");
+        assert_eq!(message.a.implied_port, 1);
+        assert_eq!(message.a.port, 6881);
+        assert_eq!(message.a.token, b"aoeusnth");
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_error() {
+        let bytes = b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
+        let message = Message::error(b"aa".to_vec(), 201, "A Generic Error Ocurred");
+
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+        assert_eq!(Message::from_bytes(bytes).unwrap(), message);
+
+        // Non-canonical messages are refused
+        assert!(Message::from_bytes(b"d1:t2:aa1:eli201e0:e1:y1:ee").is_err());
+    }
+
+    #[test]
+    fn test_nodes() {
+        let nodes = [
+            Node::new([1; 20], "127.0.0.1:6881".parse().unwrap()),
+            Node::new([2; 20], "[::1]:6882".parse().unwrap()),
+        ];
+        let mut values = Values::default();
+        values.set_nodes(&nodes);
+
+        assert_eq!(values.nodes.len(), 26);
+        assert_eq!(values.nodes6.len(), 38);
+
+        let decoded: Vec<([u8; 20], SocketAddr)> = values.nodes();
+        assert_eq!(
+            decoded,
+            [(nodes[0].id, nodes[0].addr), (nodes[1].id, nodes[1].addr)]
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let message = Message::response(
+            b"aa".to_vec(),
+            "127.0.0.1:6881".parse().unwrap(),
+            Values {
+                id: b"mnopqrstuvwxyz123456".to_vec(),
+                ..Values::default()
+            },
+        );
+        let bytes = b"d2:ip6:\x7f\0\0\x01\x1a\xe11:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re";
+
+        assert_eq!(message.to_bytes().unwrap(), bytes);
+        assert_eq!(
+            Message::from_bytes(bytes).unwrap().ip(),
+            Some("127.0.0.1:6881".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_values() {
+        let bytes = b"d1:rd2:id20:abcdefghij01234567896:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re";
+        let message = Message::from_bytes(bytes).unwrap();
+        let peers = message.r.peers();
+
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].addr.to_string(), "97.120.106.101:11893");
+    }
+}
@@ -0,0 +1,800 @@
+mod krpc;
+mod routing;
+
+use crate::bencode;
+use crate::dht::krpc::{
+    Arguments, ERROR_GENERIC, ERROR_METHOD_UNKNOWN, ERROR_PROTOCOL, Message, Values, compact_nodes,
+    parse_nodes,
+};
+use crate::dht::routing::{
+    ID_LENGTH, K, NodeId, RoutingTable, distance, is_secure_id, random_id, secure_id,
+};
+use crate::download::Download;
+use crate::metainfo::sha1;
+use crate::tracker::{Peer, compact};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWrite;
+use tokio::net::UdpSocket;
+use tokio::sync::{Mutex, oneshot};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+
+const ALPHA: usize = 3;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// Number of nodes that have to agree on our external IP before we believe them
+const EXTERNAL_IP_VOTES: usize = 3;
+const MAX_PACKET_LENGTH: usize = 2048;
+const MAX_STORED_PEERS: usize = 200;
+const MAX_VALUES: usize = 50;
+const PEER_LIFETIME: Duration = Duration::from_secs(30 * 60);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const TOKEN_LENGTH: usize = 8;
+const TOKEN_ROTATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+type Transaction = (SocketAddr, oneshot::Sender<io::Result<Values>>);
+
+// Tokens handed out in get_peers responses are a hash of the querying node's IP
+// and a secret that changes every five minutes. Tokens made with the previous
+// secret are still accepted, so a token is valid for five to ten minutes.
+#[derive(Debug)]
+struct Tokens {
+    previous: [u8; 20],
+    rotated: Instant,
+    secret: [u8; 20],
+}
+
+impl Tokens {
+    fn new() -> Self {
+        let secret = rand::random();
+        Self {
+            previous: secret,
+            rotated: Instant::now(),
+            secret,
+        }
+    }
+
+    fn rotate(&mut self) {
+        if self.rotated.elapsed() >= TOKEN_ROTATION_INTERVAL {
+            self.previous = self.secret;
+            self.secret = rand::random();
+            self.rotated = Instant::now();
+        }
+    }
+
+    fn token(&self, ip: IpAddr) -> Vec<u8> {
+        make_token(&self.secret, ip)
+    }
+
+    fn is_valid(&self, token: &[u8], ip: IpAddr) -> bool {
+        token == make_token(&self.secret, ip) || token == make_token(&self.previous, ip)
+    }
+}
+
+fn make_token(secret: &[u8], ip: IpAddr) -> Vec<u8> {
+    let mut bytes = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend_from_slice(secret);
+    sha1(&bytes)[..TOKEN_LENGTH].to_vec()
+}
+
+#[derive(Debug)]
+struct State {
+    external_ip: Option<IpAddr>,
+    // Nodes that told us our external IP, by the IP they saw
+    external_ip_votes: HashMap<IpAddr, HashSet<SocketAddr>>,
+    next_transaction: u16,
+    // Peers announced to us, by info hash
+    peers: HashMap<Vec<u8>, Vec<(SocketAddr, Instant)>>,
+    table: RoutingTable,
+    tokens: Tokens,
+    transactions: HashMap<Vec<u8>, Transaction>,
+}
+
+impl State {
+    // Counts a response that says we are at `ip`. Once enough nodes agree our ID
+    // has to be valid for it, a new one is picked otherwise.
+    fn vote_external_ip(&mut self, ip: IpAddr, voter: SocketAddr) {
+        if self.external_ip == Some(ip) {
+            return;
+        }
+
+        let voters = self.external_ip_votes.entry(ip).or_default();
+        voters.insert(voter);
+
+        if voters.len() < EXTERNAL_IP_VOTES {
+            return;
+        }
+
+        self.external_ip_votes.clear();
+        self.external_ip = Some(ip);
+
+        if !is_secure_id(&self.table.id, ip) {
+            println!("dht: external IP changed to {}, changing node ID", ip);
+            self.table.set_id(secure_id(ip));
+        }
+    }
+}
+
+// Routing table saved between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedState {
+    #[serde(with = "serde_bytes")]
+    id: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    ip: Vec<u8>,
+    #[serde(default, with = "serde_bytes")]
+    nodes: Vec<u8>,
+    #[serde(default, with = "serde_bytes")]
+    nodes6: Vec<u8>,
+}
+
+// Nodes that answered a lookup, closest first, with the tokens they gave us and
+// the peers they know
+struct Lookup {
+    nodes: Vec<(NodeId, SocketAddr, Vec<u8>)>,
+    peers: Vec<Peer>,
+}
+
+// Aborts the task reading from the socket once the last Dht handle is gone
+#[derive(Debug)]
+struct Receiver(JoinHandle<()>);
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// A node of the mainline DHT (BEP 5). Handles are cheap to clone and share the
+// same socket and routing table.
+#[derive(Debug, Clone)]
+pub struct Dht {
+    _receiver: Arc<Receiver>,
+    socket: Arc<UdpSocket>,
+    state: Arc<Mutex<State>>,
+    timeout: Duration,
+}
+
+impl Dht {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        let state = Arc::new(Mutex::new(State {
+            external_ip: None,
+            external_ip_votes: HashMap::new(),
+            next_transaction: rand::random(),
+            peers: HashMap::new(),
+            table: RoutingTable::new(random_id()),
+            tokens: Tokens::new(),
+            transactions: HashMap::new(),
+        }));
+
+        let receiver = tokio::spawn(receive(socket.clone(), state.clone()));
+
+        Ok(Self {
+            _receiver: Arc::new(Receiver(receiver)),
+            socket,
+            state,
+            timeout: QUERY_TIMEOUT,
+        })
+    }
+
+    pub async fn id(&self) -> NodeId {
+        self.state.lock().await.table.id
+    }
+
+    pub async fn len(&self) -> usize {
+        self.state.lock().await.table.len()
+    }
+
+    // Restores the node ID and the nodes saved by `save`
+    pub async fn load(&self, bytes: &[u8]) -> io::Result<()> {
+        let saved: SavedState =
+            bencode::from_bytes(bytes).map_err(|err| invalid_data(&err.to_string()))?;
+        let id = saved
+            .id
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid_data("Invalid node id"))?;
+
+        let mut state = self.state.lock().await;
+        state.table = RoutingTable::new(id);
+        state.external_ip = match saved.ip.len() {
+            4 => Some(IpAddr::from(<[u8; 4]>::try_from(saved.ip).unwrap())),
+            16 => Some(IpAddr::from(<[u8; 16]>::try_from(saved.ip).unwrap())),
+            _ => None,
+        };
+
+        for (id, addr) in parse_nodes(&saved.nodes, &saved.nodes6) {
+            state.table.insert(id, addr);
+        }
+
+        Ok(())
+    }
+
+    // Our node ID, the external IP it was made for and the good nodes of the
+    // routing table
+    pub async fn save<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let state = self.state.lock().await;
+        let nodes: Vec<_> = state
+            .table
+            .nodes()
+            .filter(|node| node.is_good())
+            .cloned()
+            .collect();
+        let (nodes, nodes6) = compact_nodes(&nodes);
+
+        let saved = SavedState {
+            id: state.table.id.to_vec(),
+            ip: match state.external_ip {
+                Some(IpAddr::V4(ip)) => ip.octets().to_vec(),
+                Some(IpAddr::V6(ip)) => ip.octets().to_vec(),
+                None => Vec::new(),
+            },
+            nodes,
+            nodes6,
+        };
+        drop(state);
+
+        Ok(bencode::to_writer_async(writer, &saved).await?)
+    }
+
+    // Joins the network and fills the routing table by looking up our own ID. The
+    // nodes already in the table are asked first, `nodes`, given as host:port,
+    // only if none of them answer.
+    pub async fn bootstrap<S>(&self, nodes: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let id = self.id().await;
+
+        if self.len().await > 0 && !self.lookup(id, None).await.nodes.is_empty() {
+            return;
+        }
+
+        let ipv4 = self.socket.local_addr().is_ok_and(|addr| addr.is_ipv4());
+        let mut queries = JoinSet::new();
+
+        for node in nodes {
+            let addrs = match tokio::net::lookup_host(node.as_ref()).await {
+                Ok(addrs) => addrs,
+                Err(err) => {
+                    eprintln!("{}: {}", node.as_ref(), err);
+                    continue;
+                }
+            };
+
+            for addr in addrs.filter(|addr| addr.is_ipv4() == ipv4) {
+                let dht = self.clone();
+                queries.spawn(async move { dht.find_node(addr, id).await });
+            }
+        }
+
+        queries.join_all().await;
+
+        self.lookup(id, None).await;
+    }
+
+    async fn find_node(&self, addr: SocketAddr, target: NodeId) -> io::Result<Values> {
+        let arguments = Arguments {
+            target: target.to_vec(),
+            ..Arguments::default()
+        };
+        self.query(addr, "find_node", arguments).await
+    }
+
+    pub async fn get_peers(&self, info_hash: &[u8]) -> Vec<Peer> {
+        match info_hash.try_into() {
+            Ok(target) => self.lookup(target, Some(info_hash)).await.peers,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Looks up peers for `info_hash` and announces that we are downloading it on
+    // `port` to the closest nodes
+    pub async fn announce(&self, info_hash: &[u8], port: u16) -> Vec<Peer> {
+        let Ok(target) = info_hash.try_into() else {
+            return Vec::new();
+        };
+
+        let lookup = self.lookup(target, Some(info_hash)).await;
+        let mut queries = JoinSet::new();
+
+        for (_, addr, token) in lookup.nodes.into_iter().take(K) {
+            let arguments = Arguments {
+                info_hash: info_hash.to_vec(),
+                port: port as i64,
+                token,
+                ..Arguments::default()
+            };
+            let dht = self.clone();
+            queries.spawn(async move { dht.query(addr, "announce_peer", arguments).await });
+        }
+
+        queries.join_all().await;
+
+        lookup.peers
+    }
+
+    // Sends a query and waits for the matching response. Nodes that answer are
+    // added to the routing table and nodes that do not are marked as failed.
+    async fn query(
+        &self,
+        addr: SocketAddr,
+        method: &str,
+        mut arguments: Arguments,
+    ) -> io::Result<Values> {
+        let (sender, receiver) = oneshot::channel();
+
+        let t = {
+            let mut state = self.state.lock().await;
+            let t = state.next_transaction.to_be_bytes().to_vec();
+            state.next_transaction = state.next_transaction.wrapping_add(1);
+            state.transactions.insert(t.clone(), (addr, sender));
+            arguments.id = state.table.id.to_vec();
+            t
+        };
+
+        let message = Message::query(t.clone(), method, arguments)
+            .to_bytes()
+            .map_err(|err| invalid_data(&err.to_string()))?;
+
+        let result = match self.socket.send_to(&message, addr).await {
+            Ok(_) => match tokio::time::timeout(self.timeout, receiver).await {
+                Ok(Ok(result)) => result,
+                _ => Err(io::ErrorKind::TimedOut.into()),
+            },
+            Err(err) => Err(err),
+        };
+
+        let mut state = self.state.lock().await;
+        state.transactions.remove(&t);
+
+        match &result {
+            Ok(values) => {
+                if let Ok(id) = values.id.as_slice().try_into() {
+                    state.table.insert(id, addr);
+                }
+            }
+            Err(_) => state.table.failed(addr),
+        }
+
+        result
+    }
+
+    // Iterative lookup of the nodes closest to `target`. ALPHA of the closest nodes
+    // not queried yet are asked at a time, until the K closest have all been asked.
+    // With `info_hash` get_peers is used instead of find_node.
+    async fn lookup(&self, target: NodeId, info_hash: Option<&[u8]>) -> Lookup {
+        let own = self.id().await;
+        let mut candidates: BTreeMap<NodeId, (NodeId, SocketAddr)> = self
+            .state
+            .lock()
+            .await
+            .table
+            .closest(&target, K)
+            .into_iter()
+            .map(|node| (distance(&node.id, &target), (node.id, node.addr)))
+            .collect();
+        let mut queried = HashSet::new();
+        let mut responded = BTreeMap::new();
+        let mut peers: Vec<Peer> = Vec::new();
+
+        loop {
+            let round: Vec<(NodeId, SocketAddr)> = candidates
+                .values()
+                .take(K)
+                .filter(|(_, addr)| !queried.contains(addr))
+                .take(ALPHA)
+                .copied()
+                .collect();
+
+            if round.is_empty() {
+                break;
+            }
+
+            let mut queries = JoinSet::new();
+
+            for (id, addr) in round {
+                queried.insert(addr);
+
+                let dht = self.clone();
+                let arguments = match info_hash {
+                    Some(info_hash) => Arguments {
+                        info_hash: info_hash.to_vec(),
+                        ..Arguments::default()
+                    },
+                    None => Arguments {
+                        target: target.to_vec(),
+                        ..Arguments::default()
+                    },
+                };
+                let method = if info_hash.is_some() {
+                    "get_peers"
+                } else {
+                    "find_node"
+                };
+
+                queries.spawn(async move { (id, addr, dht.query(addr, method, arguments).await) });
+            }
+
+            while let Some(Ok((id, addr, result))) = queries.join_next().await {
+                let values = match result {
+                    Ok(values) => values,
+                    Err(_) => {
+                        candidates.remove(&distance(&id, &target));
+                        continue;
+                    }
+                };
+
+                for (id, addr) in values.nodes() {
+                    if id != own && !queried.contains(&addr) {
+                        candidates
+                            .entry(distance(&id, &target))
+                            .or_insert((id, addr));
+                    }
+                }
+
+                for peer in values.peers() {
+                    if !peers.iter().any(|known| known.addr == peer.addr) {
+                        peers.push(peer);
+                    }
+                }
+
+                responded.insert(distance(&id, &target), (id, addr, values.token));
+            }
+        }
+
+        Lookup {
+            nodes: responded.into_values().collect(),
+            peers,
+        }
+    }
+
+    // Keeps announcing the download and adds the peers found to it
+    pub async fn run(self, download: Arc<Mutex<Download>>, nodes: Vec<String>) {
+        self.bootstrap(&nodes).await;
+
+        loop {
+            let info_hash = download.lock().await.info_hash.clone();
+            let peers = self.announce(&info_hash, crate::PORT).await;
+
+            println!("dht: {} peers", peers.len());
+
+            download.lock().await.add_peers(peers);
+
+            tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+        }
+    }
+}
+
+async fn receive(socket: Arc<UdpSocket>, state: Arc<Mutex<State>>) {
+    let mut buffer = [0u8; MAX_PACKET_LENGTH];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(_) => continue,
+        };
+
+        let Ok(message) = Message::from_bytes(&buffer[..len]) else {
+            continue;
+        };
+
+        match message.y.as_str() {
+            "q" => {
+                let reply = respond(&mut *state.lock().await, &message, addr);
+
+                if let Ok(reply) = reply.to_bytes() {
+                    let _ = socket.send_to(&reply, addr).await;
+                }
+            }
+            "r" | "e" => {
+                let mut state = state.lock().await;
+
+                // Only the node a query went to may answer it
+                if state
+                    .transactions
+                    .get(&message.t)
+                    .is_some_and(|(to, _)| *to == addr)
+                {
+                    let (_, sender) = state.transactions.remove(&message.t).unwrap();
+
+                    if let Some(ip) = message.ip() {
+                        state.vote_external_ip(ip.ip(), addr);
+                    }
+
+                    let result = if message.y == "r" {
+                        match message.r.id.len() {
+                            ID_LENGTH => Ok(message.r),
+                            _ => Err(invalid_data("Invalid node id")),
+                        }
+                    } else {
+                        Err(io::Error::other(format!("{} {}", message.e.0, message.e.1)))
+                    };
+
+                    let _ = sender.send(result);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn respond(state: &mut State, query: &Message, addr: SocketAddr) -> Message {
+    let t = query.t.clone();
+    let arguments = &query.a;
+
+    let Ok(id) = arguments.id.as_slice().try_into() else {
+        return Message::error(t, ERROR_PROTOCOL, "Invalid node id");
+    };
+
+    state.tokens.rotate();
+    state.table.insert(id, addr);
+
+    let mut values = Values {
+        id: state.table.id.to_vec(),
+        ..Values::default()
+    };
+
+    match query.q.as_str() {
+        "ping" => {}
+        "find_node" => {
+            let Ok(target) = arguments.target.as_slice().try_into() else {
+                return Message::error(t, ERROR_PROTOCOL, "Invalid target");
+            };
+
+            values.set_nodes(&state.table.closest(&target, K));
+        }
+        "get_peers" => {
+            let Ok(target) = arguments.info_hash.as_slice().try_into() else {
+                return Message::error(t, ERROR_PROTOCOL, "Invalid info hash");
+            };
+
+            values.set_nodes(&state.table.closest(&target, K));
+            values.token = state.tokens.token(addr.ip());
+
+            if let Some(peers) = state.peers.get_mut(&arguments.info_hash) {
+                peers.retain(|(_, announced)| announced.elapsed() < PEER_LIFETIME);
+                values.values = peers
+                    .iter()
+                    .take(MAX_VALUES)
+                    .map(|(peer, _)| serde_bytes::ByteBuf::from(compact(peer)))
+                    .collect();
+            }
+        }
+        "announce_peer" => {
+            if arguments.info_hash.len() != ID_LENGTH {
+                return Message::error(t, ERROR_PROTOCOL, "Invalid info hash");
+            }
+
+            if !state.tokens.is_valid(&arguments.token, addr.ip()) {
+                return Message::error(t, ERROR_PROTOCOL, "Invalid token");
+            }
+
+            let port = if arguments.implied_port != 0 {
+                addr.port()
+            } else {
+                match u16::try_from(arguments.port) {
+                    Ok(port @ 1..) => port,
+                    _ => return Message::error(t, ERROR_PROTOCOL, "Invalid port"),
+                }
+            };
+            let peer = SocketAddr::new(addr.ip(), port);
+
+            let peers = state.peers.entry(arguments.info_hash.clone()).or_default();
+            peers.retain(|(known, _)| *known != peer);
+
+            if peers.len() >= MAX_STORED_PEERS {
+                return Message::error(t, ERROR_GENERIC, "Too many peers");
+            }
+
+            peers.push((peer, Instant::now()));
+        }
+        _ => return Message::error(t, ERROR_METHOD_UNKNOWN, "Method Unknown"),
+    }
+
+    Message::response(t, addr, values)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::krpc::{Arguments, ERROR_METHOD_UNKNOWN, ERROR_PROTOCOL, Message};
+    use super::{Dht, Tokens};
+    use crate::dht::routing::is_secure_id;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    async fn node() -> Dht {
+        let mut dht = Dht::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .await
+            .unwrap();
+        dht.timeout = Duration::from_millis(500);
+        dht
+    }
+
+    // Sends a single query from a plain socket and returns the reply
+    async fn raw_query(to: &Dht, message: Message) -> Message {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket
+            .send_to(
+                &message.to_bytes().unwrap(),
+                to.socket.local_addr().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let mut buffer = [0; 1024];
+        let (len, _) = socket.recv_from(&mut buffer).await.unwrap();
+        Message::from_bytes(&buffer[..len]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let a = node().await;
+        let b = node().await;
+
+        let values = a
+            .query(b.socket.local_addr().unwrap(), "ping", Arguments::default())
+            .await
+            .unwrap();
+
+        assert_eq!(values.id, b.id().await);
+        assert_eq!(a.len().await, 1);
+        assert_eq!(b.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let a = node().await;
+        let b = node().await;
+        let addr = b.socket.local_addr().unwrap();
+        drop(b);
+
+        assert!(a.query(addr, "ping", Arguments::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_announce() {
+        let nodes = [
+            node().await,
+            node().await,
+            node().await,
+            node().await,
+            node().await,
+            node().await,
+        ];
+        let bootstrap = [nodes[0].socket.local_addr().unwrap().to_string()];
+
+        for node in &nodes[1..] {
+            node.bootstrap(&bootstrap).await;
+        }
+
+        for node in &nodes {
+            assert!(node.len().await > 1);
+        }
+
+        let info_hash = [0xab; 20];
+
+        assert!(nodes[2].announce(&info_hash, 7000).await.is_empty());
+
+        let peers = nodes[5].get_peers(&info_hash).await;
+
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 7000)));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_queries() {
+        let dht = node().await;
+        let arguments = Arguments {
+            id: vec![1; 20],
+            info_hash: vec![2; 20],
+            port: 7000,
+            token: b"invalid".to_vec(),
+            ..Arguments::default()
+        };
+
+        let reply = raw_query(
+            &dht,
+            Message::query(b"aa".to_vec(), "announce_peer", arguments.clone()),
+        )
+        .await;
+        assert_eq!(reply.y, "e");
+        assert_eq!(reply.e.0, ERROR_PROTOCOL);
+
+        let reply = raw_query(&dht, Message::query(b"ab".to_vec(), "unknown", arguments)).await;
+        assert_eq!(reply.t, b"ab");
+        assert_eq!(reply.e.0, ERROR_METHOD_UNKNOWN);
+
+        let reply = raw_query(
+            &dht,
+            Message::query(b"ac".to_vec(), "ping", Arguments::default()),
+        )
+        .await;
+        assert_eq!(reply.e.0, ERROR_PROTOCOL);
+    }
+
+    #[tokio::test]
+    async fn test_save() {
+        let a = node().await;
+        let b = node().await;
+        let b_addr = b.socket.local_addr().unwrap();
+
+        a.query(b_addr, "ping", Arguments::default()).await.unwrap();
+
+        let c = node().await;
+        let mut state = Vec::new();
+        a.save(&mut state).await.unwrap();
+        c.load(&state).await.unwrap();
+
+        assert_eq!(c.id().await, a.id().await);
+        assert_eq!(
+            c.state.lock().await.table.closest(&[0; 20], 1)[0].addr,
+            b_addr
+        );
+
+        // The restored nodes are asked before the bootstrap nodes
+        c.bootstrap(&["invalid:0"]).await;
+        let c_addr = c.socket.local_addr().unwrap();
+        assert!(
+            b.state
+                .lock()
+                .await
+                .table
+                .nodes()
+                .any(|node| node.addr == c_addr)
+        );
+
+        assert!(c.load(b"d2:id3:abce").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_external_ip() {
+        let dht = node().await;
+        let mut state = dht.state.lock().await;
+        let id = state.table.id;
+        let ip: IpAddr = "124.31.75.21".parse().unwrap();
+
+        for port in 1..=3 {
+            state.vote_external_ip(ip, SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+        }
+
+        assert_eq!(state.external_ip, Some(ip));
+        assert_ne!(state.table.id, id);
+        assert!(is_secure_id(&state.table.id, ip));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut tokens = Tokens::new();
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let token = tokens.token(ip);
+
+        assert!(tokens.is_valid(&token, ip));
+        assert!(!tokens.is_valid(&token, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+
+        // Tokens stay valid for one rotation
+        tokens.rotated -= super::TOKEN_ROTATION_INTERVAL;
+        tokens.rotate();
+        assert!(tokens.is_valid(&token, ip));
+
+        tokens.rotated -= super::TOKEN_ROTATION_INTERVAL;
+        tokens.rotate();
+        assert!(!tokens.is_valid(&token, ip));
+    }
+}
@@ -0,0 +1,347 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const ID_LENGTH: usize = 20;
+pub const K: usize = 8;
+const CRC32C_POLYNOMIAL: u32 = 0x82f63b78;
+const GOOD_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const MAX_FAILURES: u32 = 3;
+const SECURE_ID_MASK_V4: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+const SECURE_ID_MASK_V6: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+
+pub type NodeId = [u8; ID_LENGTH];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub addr: SocketAddr,
+    failures: u32,
+    pub id: NodeId,
+    last_seen: Instant,
+}
+
+impl Node {
+    pub fn new(id: NodeId, addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            failures: 0,
+            id,
+            last_seen: Instant::now(),
+        }
+    }
+
+    // A node is good if it answered recently and has not failed a query since
+    pub fn is_good(&self) -> bool {
+        self.failures == 0 && self.last_seen.elapsed() < GOOD_INTERVAL
+    }
+}
+
+pub fn distance(a: &NodeId, b: &NodeId) -> NodeId {
+    let mut distance = [0; ID_LENGTH];
+    for (byte, (a, b)) in distance.iter_mut().zip(a.iter().zip(b)) {
+        *byte = a ^ b;
+    }
+    distance
+}
+
+pub fn random_id() -> NodeId {
+    rand::random()
+}
+
+// Node ID derived from our external IP (BEP 42), so a node cannot pick an ID
+// close to an info hash it wants to control. The first 21 bits come from a hash
+// of the IP and the random number stored in the last byte.
+pub fn secure_id(ip: IpAddr) -> NodeId {
+    let mut id = random_id();
+    let crc = secure_id_crc(ip, id[ID_LENGTH - 1]);
+
+    id[0] = (crc >> 24) as u8;
+    id[1] = (crc >> 16) as u8;
+    id[2] = (crc >> 8) as u8 & 0xf8 | id[2] & 0x07;
+    id
+}
+
+// Whether `id` is a valid ID for a node at `ip`. Nodes on local networks can not
+// know their external IP, so any ID is accepted for them.
+pub fn is_secure_id(id: &NodeId, ip: IpAddr) -> bool {
+    if is_local(ip) {
+        return true;
+    }
+
+    let crc = secure_id_crc(ip, id[ID_LENGTH - 1]);
+    id[0] == (crc >> 24) as u8
+        && id[1] == (crc >> 16) as u8
+        && id[2] & 0xf8 == (crc >> 8) as u8 & 0xf8
+}
+
+pub fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+fn secure_id_crc(ip: IpAddr, rand: u8) -> u32 {
+    let mut bytes = match ip {
+        IpAddr::V4(ip) => ip
+            .octets()
+            .iter()
+            .zip(SECURE_ID_MASK_V4)
+            .map(|(byte, mask)| byte & mask)
+            .collect::<Vec<u8>>(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .zip(SECURE_ID_MASK_V6)
+            .map(|(byte, mask)| byte & mask)
+            .collect(),
+    };
+    bytes[0] |= (rand & 0x07) << 5;
+    crc32c(&bytes)
+}
+
+fn crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ CRC32C_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Kademlia routing table with one bucket of up to K nodes per length of the
+// prefix shared with our own ID, so nodes close to us are known in more detail
+#[derive(Debug)]
+pub struct RoutingTable {
+    buckets: Vec<Vec<Node>>,
+    pub id: NodeId,
+}
+
+impl RoutingTable {
+    pub fn new(id: NodeId) -> Self {
+        Self {
+            buckets: vec![Vec::new(); ID_LENGTH * 8],
+            id,
+        }
+    }
+
+    // Changes our own ID, the nodes already known are sorted into the buckets of
+    // the new one
+    pub fn set_id(&mut self, id: NodeId) {
+        let nodes: Vec<Node> = self
+            .buckets
+            .iter_mut()
+            .flat_map(|bucket| bucket.drain(..))
+            .collect();
+        self.id = id;
+
+        for node in nodes {
+            if let Some(index) = self.bucket(&node.id)
+                && self.buckets[index].len() < K
+            {
+                self.buckets[index].push(node);
+            }
+        }
+    }
+
+    fn bucket(&self, id: &NodeId) -> Option<usize> {
+        let distance = distance(&self.id, id);
+        distance
+            .iter()
+            .position(|byte| *byte != 0)
+            .map(|index| index * 8 + distance[index].leading_zeros() as usize)
+    }
+
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<Node> {
+        let mut nodes: Vec<Node> = self.nodes().cloned().collect();
+        nodes.sort_by_key(|node| distance(&node.id, target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    // Counts a query `addr` did not answer. Nodes that keep failing are removed.
+    pub fn failed(&mut self, addr: SocketAddr) {
+        for bucket in &mut self.buckets {
+            if let Some(node) = bucket.iter_mut().find(|node| node.addr == addr) {
+                node.failures += 1;
+            }
+            bucket.retain(|node| node.failures < MAX_FAILURES);
+        }
+    }
+
+    // Records that we heard from `id` at `addr`. A full bucket only takes the node
+    // if one of its nodes is no longer good, the one heard from least recently is
+    // replaced then. Returns whether the node is in the table.
+    pub fn insert(&mut self, id: NodeId, addr: SocketAddr) -> bool {
+        let Some(index) = self.bucket(&id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+
+        if let Some(node) = bucket.iter_mut().find(|node| node.id == id) {
+            node.addr = addr;
+            node.failures = 0;
+            node.last_seen = Instant::now();
+            return true;
+        }
+
+        if bucket.len() < K {
+            bucket.push(Node::new(id, addr));
+            return true;
+        }
+
+        let replaced = bucket
+            .iter_mut()
+            .filter(|node| !node.is_good())
+            .min_by_key(|node| node.last_seen);
+
+        match replaced {
+            Some(node) => {
+                *node = Node::new(id, addr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.buckets.iter().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ID_LENGTH, K, NodeId, RoutingTable, crc32c, distance, is_secure_id, secure_id};
+    use std::net::{IpAddr, SocketAddr};
+
+    fn id(first: u8, last: u8) -> NodeId {
+        let mut id = [0; ID_LENGTH];
+        id[0] = first;
+        id[ID_LENGTH - 1] = last;
+        id
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(&id(0x0f, 1), &id(0xf0, 3)), id(0xff, 2));
+    }
+
+    #[test]
+    fn test_bucket() {
+        let table = RoutingTable::new(id(0, 0));
+
+        assert_eq!(table.bucket(&id(0, 0)), None);
+        assert_eq!(table.bucket(&id(0x80, 0)), Some(0));
+        assert_eq!(table.bucket(&id(0x01, 0)), Some(7));
+        assert_eq!(table.bucket(&id(0, 1)), Some(159));
+    }
+
+    #[test]
+    fn test_full_bucket() {
+        let mut table = RoutingTable::new(id(0, 0));
+
+        for n in 0..K as u8 {
+            assert!(table.insert(id(0x80, n), addr(n as u16 + 1)));
+        }
+
+        assert!(!table.insert(id(0x80, 0xff), addr(100)));
+        assert!(table.insert(id(0x40, 0), addr(101)));
+
+        // A node that stopped answering makes room for a new one
+        table.failed(addr(3));
+        assert!(table.insert(id(0x80, 0xff), addr(100)));
+        assert_eq!(table.len(), K + 1);
+        assert!(!table.nodes().any(|node| node.addr == addr(3)));
+    }
+
+    #[test]
+    fn test_failed() {
+        let mut table = RoutingTable::new(id(0, 0));
+        table.insert(id(0x80, 0), addr(1));
+
+        for _ in 0..3 {
+            table.failed(addr(1));
+        }
+
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn test_closest() {
+        let mut table = RoutingTable::new(id(0, 0));
+        table.insert(id(0x80, 0), addr(1));
+        table.insert(id(0x40, 0), addr(2));
+        table.insert(id(0x41, 0), addr(3));
+
+        let closest: Vec<SocketAddr> = table
+            .closest(&id(0x41, 1), 2)
+            .iter()
+            .map(|node| node.addr)
+            .collect();
+
+        assert_eq!(closest, [addr(3), addr(2)]);
+    }
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+    }
+
+    #[test]
+    fn test_secure_id() {
+        // Test vectors from BEP 42
+        let vectors = [
+            ("124.31.75.21", 1, [0x5f, 0xbf, 0xbf]),
+            ("21.75.31.124", 86, [0x5a, 0x3c, 0xe9]),
+            ("65.23.51.170", 22, [0xa5, 0xd4, 0x32]),
+            ("84.124.73.14", 65, [0x1b, 0x03, 0x21]),
+            ("43.213.53.83", 90, [0xe5, 0x6f, 0x6c]),
+        ];
+
+        for (ip, rand, prefix) in vectors {
+            let ip: IpAddr = ip.parse().unwrap();
+            let mut id = id(prefix[0], rand);
+            id[1] = prefix[1];
+            id[2] = prefix[2];
+
+            assert!(is_secure_id(&id, ip));
+
+            id[1] ^= 1;
+            assert!(!is_secure_id(&id, ip));
+
+            assert!(is_secure_id(&secure_id(ip), ip));
+        }
+
+        assert!(is_secure_id(&id(0, 0), "192.168.1.1".parse().unwrap()));
+        assert!(is_secure_id(
+            &secure_id("2001:db8::1".parse().unwrap()),
+            "2001:db8::1".parse().unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_set_id() {
+        let mut table = RoutingTable::new(id(0, 0));
+        table.insert(id(0x80, 0), addr(1));
+        table.insert(id(0x40, 0), addr(2));
+
+        table.set_id(id(0x80, 0));
+
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.bucket(&id(0x40, 0)), Some(0));
+    }
+}
@@ -1,51 +1,251 @@
-use crate::metainfo::Info;
-use crate::metainfo::Metainfo;
+use crate::metainfo::{Info, sha1};
+use crate::piece::PieceManager;
+use crate::storage::Storage;
 use crate::tracker::Peer;
-use std::io::Write;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify, broadcast};
 
-#[derive(Debug)]
 pub struct Download {
+    // Peers with a running session, shared with other peers through peer exchange
+    pub connected: HashSet<SocketAddr>,
     pub downloaded: i64,
+    pub haves: broadcast::Sender<u32>,
     pub info_hash: Vec<u8>,
     length: i64,
+    // The bencoded info dictionary, served to peers fetching it with ut_metadata
+    pub metadata: Vec<u8>,
     pub peer_id: Vec<u8>,
     pub peers: Vec<Peer>,
+    pub peers_changed: Arc<Notify>,
+    piece_length: i64,
+    pub pieces: PieceManager,
+    // Peers of private torrents must not be shared or looked for outside the trackers
+    pub private: bool,
+    storage: Arc<Storage>,
     pub uploaded: i64,
 }
 
-impl Download {
-    pub fn new(metainfo: &Metainfo) -> Self {
-        let info_hash = match metainfo.info_hash() {
-            Ok(bytes) => bytes,
-            Err(err) => {
-                eprintln!("{}", err);
-                std::process::exit(1);
-            }
-        };
-
-        let mut peer_id = [0u8; 20];
-        rand::fill(&mut peer_id);
-        peer_id
-            .as_mut()
-            .write(crate::PEER_ID_PREFIX.as_bytes())
-            .unwrap();
+impl fmt::Debug for Download {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Download")
+            .field("connected", &self.connected.len())
+            .field("downloaded", &self.downloaded)
+            .field("left", &self.left())
+            .field("peers", &self.peers.len())
+            .field("pieces", &self.pieces)
+            .field("uploaded", &self.uploaded)
+            .finish()
+    }
+}
 
-        let length = match metainfo.info {
-            Info::Single { length, .. } => length,
-            _ => 0,
-        };
+impl Download {
+    pub fn new(metadata: Vec<u8>, peer_id: Vec<u8>, info: &Info, storage: Storage) -> Self {
+        let info_hash = sha1(&metadata);
+        let length = info.length();
+        let piece_length = info.piece_length();
+        let pieces = PieceManager::new(info);
 
         Self {
+            connected: HashSet::new(),
             downloaded: 0,
+            haves: broadcast::channel(64).0,
             info_hash,
             length,
-            peer_id: peer_id.to_vec(),
+            metadata,
+            peer_id,
             peers: Vec::new(),
+            peers_changed: Arc::new(Notify::new()),
+            piece_length,
+            pieces,
+            private: info.is_private(),
+            storage: Arc::new(storage),
             uploaded: 0,
         }
     }
 
+    pub fn add_peers<I>(&mut self, peers: I)
+    where
+        I: IntoIterator<Item = Peer>,
+    {
+        let len = self.peers.len();
+
+        for peer in peers {
+            if !self.peers.iter().any(|known| known.addr == peer.addr) {
+                self.peers.push(peer);
+            }
+        }
+
+        if self.peers.len() > len {
+            self.peers_changed.notify_one();
+        }
+    }
+
+    // Peers another peer lost its connection to are likely gone, they are tried
+    // after all others unless we are connected to them
+    pub fn drop_peers(&mut self, dropped: &[SocketAddr]) {
+        let (stale, mut peers): (Vec<Peer>, Vec<Peer>) = std::mem::take(&mut self.peers)
+            .into_iter()
+            .partition(|peer| dropped.contains(&peer.addr) && !self.connected.contains(&peer.addr));
+        peers.extend(stale);
+        self.peers = peers;
+    }
+
+    pub fn read_block(&mut self, index: u32, begin: u32, length: u32) -> io::Result<Vec<u8>> {
+        let offset = index as i64 * self.piece_length + begin as i64;
+        let data = self.storage.read(offset, length as usize)?;
+        self.uploaded += length as i64;
+        Ok(data)
+    }
+
     pub fn left(&self) -> i64 {
-        self.length
+        self.length - self.downloaded
+    }
+}
+
+// Writes a piece that matched its hash and only then marks it verified. The
+// download is not locked while the disk is written, a piece that can't be written
+// is fetched again.
+pub async fn write_piece(download: &Mutex<Download>, index: u32, data: Vec<u8>) -> io::Result<()> {
+    let (storage, offset) = {
+        let download = download.lock().await;
+        (
+            download.storage.clone(),
+            index as i64 * download.piece_length,
+        )
+    };
+
+    let result = tokio::task::spawn_blocking(move || storage.write(offset, &data))
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)));
+
+    let mut download = download.lock().await;
+
+    if result.is_ok() {
+        download.pieces.verify(index);
+        download.downloaded = download.pieces.verified_bytes();
+        let _ = download.haves.send(index);
+    } else {
+        download.pieces.reset(index);
+    }
+
+    result
+}
+
+// Counts the pieces already on disk as verified. Reading and hashing all of them
+// takes a while for a large torrent, so it happens piece by piece off the runtime.
+pub async fn resume(download: &Mutex<Download>) {
+    let len = download.lock().await.pieces.len() as u32;
+
+    for index in 0..len {
+        let (storage, offset, length) = {
+            let download = download.lock().await;
+            (
+                download.storage.clone(),
+                index as i64 * download.piece_length,
+                download.pieces.piece_length(index) as usize,
+            )
+        };
+
+        let hash = tokio::task::spawn_blocking(move || {
+            storage.read(offset, length).map(|data| sha1(&data))
+        })
+        .await;
+
+        if let Ok(Ok(hash)) = hash {
+            let mut download = download.lock().await;
+            download.pieces.check(index, &hash);
+            download.downloaded = download.pieces.verified_bytes();
+        }
+    }
+}
+
+pub fn peer_id() -> Vec<u8> {
+    let mut peer_id = [0u8; 20];
+    rand::fill(&mut peer_id);
+    peer_id
+        .as_mut()
+        .write_all(crate::PEER_ID_PREFIX.as_bytes())
+        .unwrap();
+    peer_id.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Download, resume};
+    use crate::metainfo::{Info, sha1};
+    use crate::storage::Storage;
+    use crate::tracker::Peer;
+    use std::fs;
+    use std::net::SocketAddr;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_resume() {
+        let data: Vec<u8> = (0..40).collect();
+        let mut hashes: Vec<u8> = data.chunks(16).flat_map(sha1).collect();
+        // The second piece on disk doesn't match
+        hashes[20] ^= 1;
+        let info = Info::Single {
+            length: 40,
+            name: String::from("file"),
+            piece_length: 16,
+            pieces: &hashes,
+            private: None,
+            source: None,
+        };
+
+        let root = std::env::temp_dir().join(format!("shiina-resume-{}", std::process::id()));
+        let storage = Storage::new(&info, &root).unwrap();
+        storage.write(0, &data).unwrap();
+
+        let download = Mutex::new(Download::new(Vec::new(), vec![0; 20], &info, storage));
+        assert_eq!(download.lock().await.left(), 40);
+
+        resume(&download).await;
+
+        let download = download.lock().await;
+        assert!(download.pieces.has(0));
+        assert!(!download.pieces.has(1));
+        assert!(download.pieces.has(2));
+        assert_eq!(download.left(), 16);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_drop_peers() {
+        let hashes = sha1(b"abc");
+        let info = Info::Single {
+            length: 3,
+            name: String::from("file"),
+            piece_length: 3,
+            pieces: &hashes,
+            private: None,
+            source: None,
+        };
+        let root = std::env::temp_dir().join(format!("shiina-drop-{}", std::process::id()));
+        let storage = Storage::new(&info, &root).unwrap();
+        let mut download = Download::new(Vec::new(), vec![0; 20], &info, storage);
+
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:1", "10.0.0.2:2", "10.0.0.3:3"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        download.add_peers(addrs.iter().map(|&addr| Peer {
+            addr,
+            peer_id: None,
+        }));
+        download.connected.insert(addrs[1]);
+
+        download.drop_peers(&addrs[..2]);
+
+        let order: Vec<SocketAddr> = download.peers.iter().map(|peer| peer.addr).collect();
+        assert_eq!(order, [addrs[1], addrs[2], addrs[0]]);
+
+        fs::remove_dir_all(root).unwrap();
     }
 }
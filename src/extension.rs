@@ -0,0 +1,171 @@
+use crate::bencode::{self, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+// Extended message ID of the handshake, the IDs of all other messages are
+// negotiated in it
+pub const HANDSHAKE_ID: u8 = 0;
+const REQUEST_QUEUE_LENGTH: i64 = 250;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    Metadata,
+    Pex,
+}
+
+impl Extension {
+    fn name(&self) -> &'static str {
+        match self {
+            Extension::Metadata => "ut_metadata",
+            Extension::Pex => "ut_pex",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Handshake {
+    #[serde(default)]
+    pub m: BTreeMap<String, i64>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub metadata_size: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub p: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub reqq: i64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub v: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    pub yourip: Vec<u8>,
+}
+
+impl Handshake {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        bencode::from_bytes(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bencode::to_bytes(self)
+    }
+}
+
+// Maps extended message IDs to the extensions they belong to. Peers send their
+// messages with the IDs from our handshake and we send ours with the IDs from
+// theirs, so both directions are tracked separately.
+#[derive(Debug)]
+pub struct Registry {
+    local: Vec<Extension>,
+    remote: Vec<(Extension, u8)>,
+}
+
+impl Registry {
+    pub fn new(extensions: &[Extension]) -> Self {
+        Self {
+            local: extensions.to_vec(),
+            remote: Vec::new(),
+        }
+    }
+
+    // Our handshake, `addr` is the peer's address echoed back as `yourip`
+    pub fn handshake(&self, addr: IpAddr, metadata_size: i64) -> Handshake {
+        let yourip = match addr {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+
+        Handshake {
+            m: self
+                .local
+                .iter()
+                .zip(1..)
+                .map(|(extension, id)| (extension.name().to_string(), id))
+                .collect(),
+            metadata_size,
+            p: crate::PORT as i64,
+            reqq: REQUEST_QUEUE_LENGTH,
+            v: format!("{} {}", crate::PROGRAM, env!("CARGO_PKG_VERSION")),
+            yourip,
+        }
+    }
+
+    // Applies a handshake from the peer. It may be sent more than once and an ID of
+    // 0 disables an extension, so only the extensions it mentions are updated.
+    pub fn negotiate(&mut self, handshake: &Handshake) {
+        for extension in &self.local {
+            if let Some(id) = handshake.m.get(extension.name()) {
+                self.remote.retain(|(known, _)| known != extension);
+
+                if let Ok(id @ 1..) = u8::try_from(*id) {
+                    self.remote.push((*extension, id));
+                }
+            }
+        }
+    }
+
+    // ID to use when sending a message of `extension` to the peer
+    pub fn id(&self, extension: Extension) -> Option<u8> {
+        self.remote
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, id)| *id)
+    }
+
+    // Extension a message the peer sent with `id` belongs to
+    pub fn route(&self, id: u8) -> Option<Extension> {
+        self.local.get((id as usize).checked_sub(1)?).copied()
+    }
+}
+
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Extension, Handshake, Registry};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_handshake() {
+        let registry = Registry::new(&[Extension::Metadata]);
+        let handshake = registry.handshake(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 0);
+        let bytes = handshake.to_bytes().unwrap();
+
+        assert!(bytes.starts_with(b"d1:md11:ut_metadatai1ee1:pi6881e4:reqqi250e1:v"));
+        assert!(bytes.ends_with(b"6:yourip4:\x0a\0\0\x01e"));
+
+        let handshake =
+            Handshake::from_bytes(b"d1:md11:ut_metadatai3ee13:metadata_sizei31235e1:v4:teste")
+                .unwrap();
+
+        assert_eq!(handshake.m["ut_metadata"], 3);
+        assert_eq!(handshake.metadata_size, 31235);
+        assert_eq!(handshake.v, "test");
+        assert_eq!(handshake.reqq, 0);
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new(&[Extension::Metadata]);
+
+        assert_eq!(registry.route(0), None);
+        assert_eq!(registry.route(1), Some(Extension::Metadata));
+        assert_eq!(registry.route(2), None);
+        assert_eq!(registry.id(Extension::Metadata), None);
+
+        let mut handshake = Handshake::default();
+        handshake.m.insert(String::from("ut_metadata"), 3);
+        handshake.m.insert(String::from("unknown"), 4);
+        registry.negotiate(&handshake);
+
+        assert_eq!(registry.id(Extension::Metadata), Some(3));
+
+        // Handshakes that do not mention an extension leave it alone
+        registry.negotiate(&Handshake::default());
+        assert_eq!(registry.id(Extension::Metadata), Some(3));
+
+        handshake.m.insert(String::from("ut_metadata"), 0);
+        registry.negotiate(&handshake);
+        assert_eq!(registry.id(Extension::Metadata), None);
+    }
+}
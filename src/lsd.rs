@@ -0,0 +1,266 @@
+use crate::download::Download;
+use crate::magnet::decode_info_hash;
+use crate::tracker::Peer;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+
+pub const GROUP_V4: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 192, 152, 143)), 6771);
+pub const GROUP_V6: SocketAddr = SocketAddr::new(
+    IpAddr::V6(Ipv6Addr::new(0xff15, 0, 0, 0, 0, 0, 0xefc0, 0x988f)),
+    6771,
+);
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const COOKIE_LENGTH: usize = 8;
+const MAX_MESSAGE_LENGTH: usize = 1400;
+
+#[derive(Debug, PartialEq, Eq)]
+struct Announce {
+    cookie: String,
+    info_hashes: Vec<Vec<u8>>,
+    port: u16,
+}
+
+impl Announce {
+    fn to_bytes(&self, group: SocketAddr) -> Vec<u8> {
+        let mut message = format!(
+            "BT-SEARCH * HTTP/1.1\r\nHost: {}\r\nPort: {}\r\n",
+            group, self.port
+        );
+
+        for info_hash in &self.info_hashes {
+            message.push_str("Infohash: ");
+            info_hash
+                .iter()
+                .for_each(|byte| message.push_str(&format!("{:02x}", byte)));
+            message.push_str("\r\n");
+        }
+
+        message.push_str(&format!("cookie: {}\r\n\r\n\r\n", self.cookie));
+        message.into_bytes()
+    }
+
+    // Header names are case insensitive and unknown headers are ignored
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let message = std::str::from_utf8(bytes).ok()?;
+        let mut lines = message.split("\r\n");
+
+        if lines.next()? != "BT-SEARCH * HTTP/1.1" {
+            return None;
+        }
+
+        let mut announce = Announce {
+            cookie: String::new(),
+            info_hashes: Vec::new(),
+            port: 0,
+        };
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "cookie" => announce.cookie = value.to_string(),
+                "infohash" => announce.info_hashes.push(decode_info_hash(value).ok()?),
+                "port" => announce.port = value.parse().ok()?,
+                _ => {}
+            }
+        }
+
+        (announce.port != 0 && !announce.info_hashes.is_empty()).then_some(announce)
+    }
+}
+
+// Local Service Discovery (BEP 14): peers on the same network find each other
+// by multicasting the info hashes they are downloading
+#[derive(Debug)]
+pub struct Lsd {
+    // Sent with every announce so we can recognize our own
+    cookie: String,
+    group: SocketAddr,
+    socket: UdpSocket,
+}
+
+impl Lsd {
+    pub async fn bind(group: SocketAddr) -> io::Result<Self> {
+        // Other clients on the host may already listen on the LSD port
+        let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&group.into())?;
+        let socket = UdpSocket::from_std(socket.into())?;
+
+        match group {
+            SocketAddr::V4(group) => {
+                socket.join_multicast_v4(*group.ip(), Ipv4Addr::UNSPECIFIED)?;
+                socket.set_multicast_loop_v4(true)?;
+            }
+            SocketAddr::V6(group) => {
+                socket.join_multicast_v6(group.ip(), 0)?;
+                socket.set_multicast_loop_v6(true)?;
+            }
+        }
+
+        let cookie = (0..COOKIE_LENGTH)
+            .map(|_| format!("{:x}", rand::random::<u8>() & 0x0f))
+            .collect();
+
+        Ok(Self {
+            cookie,
+            group,
+            socket,
+        })
+    }
+
+    pub async fn announce(&self, info_hash: &[u8], port: u16) -> io::Result<()> {
+        let announce = Announce {
+            cookie: self.cookie.clone(),
+            info_hashes: vec![info_hash.to_vec()],
+            port,
+        };
+
+        self.socket
+            .send_to(&announce.to_bytes(self.group), self.group)
+            .await?;
+
+        Ok(())
+    }
+
+    // Waits for an announce of `info_hash` by another peer
+    pub async fn receive(&self, info_hash: &[u8]) -> io::Result<Peer> {
+        let mut buffer = [0; MAX_MESSAGE_LENGTH];
+
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buffer).await?;
+
+            let Some(announce) = Announce::from_bytes(&buffer[..len]) else {
+                continue;
+            };
+
+            if announce.cookie != self.cookie
+                && announce.info_hashes.iter().any(|hash| hash == info_hash)
+            {
+                return Ok(Peer {
+                    addr: SocketAddr::new(addr.ip(), announce.port),
+                    peer_id: None,
+                });
+            }
+        }
+    }
+
+    // Announces the download periodically and adds the peers that announce it
+    pub async fn run(self, download: Arc<Mutex<Download>>) {
+        let info_hash = download.lock().await.info_hash.clone();
+        let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(err) = self.announce(&info_hash, crate::PORT).await {
+                        eprintln!("{}: {}", self.group, err);
+                    }
+                }
+                result = self.receive(&info_hash) => match result {
+                    Ok(peer) => {
+                        println!("{}: found {}", self.group, peer.addr);
+                        download.lock().await.add_peers([peer]);
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", self.group, err);
+                        return;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Announce, GROUP_V4, Lsd};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn test_announce() {
+        let announce = Announce {
+            cookie: String::from("abc"),
+            info_hashes: vec![vec![0xab; 20]],
+            port: 6881,
+        };
+        let bytes = announce.to_bytes(GROUP_V4);
+
+        assert_eq!(
+            bytes,
+            format!(
+                "BT-SEARCH * HTTP/1.1\r\nHost: 239.192.152.143:6771\r\nPort: 6881\r\nInfohash: {}\r\ncookie: abc\r\n\r\n\r\n",
+                "ab".repeat(20)
+            )
+            .as_bytes()
+        );
+        assert_eq!(Announce::from_bytes(&bytes), Some(announce));
+
+        let bytes = b"BT-SEARCH * HTTP/1.1\r\nhost: 239.192.152.143:6771\r\nport: 51413\r\ninfohash: 0101010101010101010101010101010101010101\r\nInfohash: 0202020202020202020202020202020202020202\r\n\r\n\r\n";
+        let announce = Announce::from_bytes(bytes).unwrap();
+
+        assert_eq!(announce.port, 51413);
+        assert_eq!(announce.info_hashes, [[1; 20], [2; 20]]);
+        assert_eq!(announce.cookie, "");
+
+        assert_eq!(Announce::from_bytes(b"M-SEARCH * HTTP/1.1\r\n\r\n"), None);
+    }
+
+    async fn multicast(group: &str) {
+        let group: SocketAddr = group.parse().unwrap();
+        let lsd = Lsd::bind(group).await.unwrap();
+        let info_hash = [0xab; 20];
+
+        // Our own announce comes back over the loopback and is ignored
+        lsd.announce(&info_hash, 6881).await.unwrap();
+
+        let other = Announce {
+            cookie: String::from("other"),
+            info_hashes: vec![info_hash.to_vec()],
+            port: 7000,
+        };
+        let unspecified = match group {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(unspecified).await.unwrap();
+        socket.send_to(&other.to_bytes(group), group).await.unwrap();
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), lsd.receive(&info_hash))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(peer.addr.port(), 7000);
+    }
+
+    #[tokio::test]
+    async fn test_multicast() {
+        multicast("239.192.152.143:16771").await;
+    }
+
+    #[tokio::test]
+    async fn test_multicast_v6() {
+        multicast("[ff15::efc0:988f]:16771").await;
+    }
+
+    #[tokio::test]
+    async fn test_shared_port() {
+        let group: SocketAddr = "239.192.152.143:16772".parse().unwrap();
+        let _first = Lsd::bind(group).await.unwrap();
+
+        assert!(Lsd::bind(group).await.is_ok());
+    }
+}
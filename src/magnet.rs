@@ -0,0 +1,183 @@
+use crate::bencode::{self, Spanned};
+use crate::metainfo::{Info, Metainfo};
+use crate::tracker::Peer;
+use reqwest::Url;
+use std::io;
+use std::net::SocketAddr;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Magnet {
+    pub info_hash: Vec<u8>,
+    pub name: Option<String>,
+    pub peers: Vec<Peer>,
+    pub trackers: Vec<String>,
+}
+
+impl Magnet {
+    pub fn parse(uri: &str) -> io::Result<Self> {
+        let url = Url::parse(uri).map_err(|err| invalid_data(&err.to_string()))?;
+
+        if url.scheme() != "magnet" {
+            return Err(invalid_data("Not a magnet link"));
+        }
+
+        let mut info_hash = None;
+        let mut name = None;
+        let mut peers = Vec::new();
+        let mut trackers = Vec::new();
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "xt" => {
+                    if info_hash.is_none()
+                        && let Some(hash) = value.strip_prefix("urn:btih:")
+                    {
+                        info_hash = Some(decode_info_hash(hash)?);
+                    }
+                }
+                "dn" => name = Some(value.into_owned()),
+                "tr" => trackers.push(value.into_owned()),
+                // Host names are not resolved, only literal addresses are accepted
+                "x.pe" => {
+                    if let Ok(addr) = value.parse::<SocketAddr>() {
+                        peers.push(Peer {
+                            addr,
+                            peer_id: None,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.ok_or_else(|| invalid_data("Missing urn:btih info hash"))?,
+            name,
+            peers,
+            trackers,
+        })
+    }
+
+    // Builds the metainfo from the info dictionary fetched from peers, which must
+    // already have been checked against `info_hash`
+    pub fn metainfo<'a>(&self, metadata: &'a [u8]) -> Result<Metainfo<'a>, bencode::Error> {
+        let info = bencode::from_bytes::<Spanned<Info>>(metadata)?;
+
+        let announce_list = if self.trackers.is_empty() {
+            Vec::new()
+        } else {
+            vec![self.trackers.clone()]
+        };
+
+        Ok(Metainfo::new(
+            self.trackers.first().cloned(),
+            announce_list,
+            info,
+        ))
+    }
+}
+
+// The info hash is either 40 hex digits or 32 base32 characters
+pub fn decode_info_hash(hash: &str) -> io::Result<Vec<u8>> {
+    let bytes = hash.as_bytes();
+
+    let decoded = match bytes.len() {
+        // Digit by digit, as from_str_radix would also take a sign
+        40 => bytes
+            .chunks_exact(2)
+            .map(|pair| {
+                let high = char::from(pair[0]).to_digit(16)?;
+                let low = char::from(pair[1]).to_digit(16)?;
+                u8::try_from(high << 4 | low).ok()
+            })
+            .collect::<Option<Vec<u8>>>(),
+        32 => {
+            let mut decoded = Vec::with_capacity(20);
+            let mut buffer = 0u64;
+            let mut bits = 0;
+
+            for byte in bytes {
+                let value = BASE32_ALPHABET
+                    .iter()
+                    .position(|c| c.eq_ignore_ascii_case(byte))
+                    .ok_or_else(|| invalid_data("Invalid base32 info hash"))?;
+
+                buffer = buffer << 5 | value as u64;
+                bits += 5;
+
+                if bits >= 8 {
+                    bits -= 8;
+                    decoded.push((buffer >> bits) as u8);
+                }
+            }
+
+            Some(decoded)
+        }
+        _ => None,
+    };
+
+    decoded.ok_or_else(|| invalid_data("Invalid info hash"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Magnet;
+
+    const INFO_HASH: [u8; 20] = [
+        0xc1, 0x2f, 0xe1, 0xc0, 0x6b, 0xba, 0x25, 0x4a, 0x9d, 0xc9, 0xf5, 0x19, 0xb3, 0x35, 0xaa,
+        0x7c, 0x13, 0x67, 0xa8, 0x8a,
+    ];
+
+    #[test]
+    fn test_hex() {
+        let magnet = Magnet::parse(
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&dn=Some+file&tr=http%3A%2F%2Fexample.com%2Fannounce&tr=udp%3A%2F%2Fexample.com%3A80&x.pe=127.0.0.1%3A6881&x.pe=example.com%3A6881",
+        )
+        .unwrap();
+
+        assert_eq!(magnet.info_hash, INFO_HASH);
+        assert_eq!(magnet.name.as_deref(), Some("Some file"));
+        assert_eq!(
+            magnet.trackers,
+            ["http://example.com/announce", "udp://example.com:80"]
+        );
+        assert_eq!(magnet.peers.len(), 1);
+        assert_eq!(magnet.peers[0].addr.to_string(), "127.0.0.1:6881");
+    }
+
+    #[test]
+    fn test_base32() {
+        let magnet = Magnet::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").unwrap();
+
+        assert_eq!(magnet.info_hash, INFO_HASH);
+        assert_eq!(magnet.name, None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Magnet::parse("http://example.com").is_err());
+        assert!(Magnet::parse("magnet:?dn=name").is_err());
+        assert!(Magnet::parse("magnet:?xt=urn:btih:abc").is_err());
+        assert!(
+            Magnet::parse("magnet:?xt=urn:btih:+f2fe1c06bba254a9dc9f519b335aa7c1367a88a").is_err()
+        );
+        assert!(Magnet::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKE1").is_err());
+    }
+
+    #[test]
+    fn test_metainfo() {
+        let magnet = Magnet::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").unwrap();
+        // The keys are not sorted, which the info hash already accounts for
+        let metadata = b"d4:name1:a6:lengthi3e12:piece lengthi3e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let metainfo = magnet.metainfo(metadata).unwrap();
+
+        assert_eq!(metainfo.info.name(), "a");
+        assert_eq!(metainfo.info.bytes(), metadata);
+    }
+}
@@ -1,55 +1,392 @@
 mod bencode;
+mod dht;
 mod download;
+mod extension;
+mod lsd;
+mod magnet;
+mod metadata;
 mod metainfo;
+mod peer;
+mod pex;
+mod piece;
+mod storage;
 mod tracker;
+mod webseed;
 
+use crate::bencode::{Decoder, Value};
+use crate::dht::Dht;
 use crate::download::Download;
+use crate::lsd::Lsd;
+use crate::magnet::Magnet;
 use crate::metainfo::Metainfo;
-use crate::tracker::Tracker;
+use crate::storage::Storage;
+use crate::tracker::Trackers;
+use crate::webseed::WebSeed;
 use std::env;
 use std::error;
 use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::process;
+use std::slice;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, mpsc, oneshot};
 
+const DHT_BOOTSTRAP_NODES: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+const DHT_STATE_FILE: &str = ".shiina-dht";
+const DHT_TEMP_FILE: &str = ".shiina-dht.tmp";
 const IP: Option<String> = None;
+const METADATA_RETRY_INTERVAL: Duration = Duration::from_secs(60);
 const PEER_ID_PREFIX: &str = "-sh0010-";
 const PORT: u16 = 6881;
 const PROGRAM: &str = "shiina";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn error::Error>> {
-    let file_name = if let Some(file_name) = env::args().skip(1).next() {
-        file_name
+    let args: Vec<String> = env::args().collect();
+
+    let (command, file_name, path) = match args.as_slice() {
+        [_, command, file_name] if command == "dump" || command == "scrape" => {
+            (command.as_str(), file_name, None)
+        }
+        [_, command, file_name, path] if command == "dump" => {
+            (command.as_str(), file_name, Some(path))
+        }
+        [_, file_name] => ("download", file_name, None),
+        _ => {
+            usage();
+            process::exit(1);
+        }
+    };
+
+    let peer_id = download::peer_id();
+
+    let magnet = if file_name.starts_with("magnet:") {
+        match Magnet::parse(file_name) {
+            Ok(magnet) => Some(magnet),
+            Err(err) => {
+                eprintln!("{}: {}", file_name, err);
+                process::exit(1);
+            }
+        }
     } else {
-        usage();
-        process::exit(1);
+        None
     };
 
-    let contents = match fs::read(&file_name) {
-        Ok(contents) => contents,
-        Err(message) => {
-            eprintln!("{}: {}", file_name, message);
-            process::exit(1);
+    if command == "scrape" {
+        return match &magnet {
+            Some(magnet) => {
+                let mut trackers = Trackers::new("", slice::from_ref(&magnet.trackers));
+                scrape_torrent(&mut trackers, &magnet.info_hash).await
+            }
+            None => {
+                let contents = match fs::read(file_name) {
+                    Ok(contents) => contents,
+                    Err(err) => {
+                        eprintln!("{}: {}", file_name, err);
+                        process::exit(1);
+                    }
+                };
+                let torrent = bencode::from_bytes::<Metainfo>(&contents)
+                    .unwrap_or_else(|err| decode_error(file_name, &contents, err));
+                let mut trackers = Trackers::new(
+                    torrent.announce.as_deref().unwrap_or_default(),
+                    &torrent.announce_list,
+                );
+                scrape_torrent(&mut trackers, &torrent.info_hash()).await
+            }
+        };
+    }
+
+    // `-` reads the value from standard input, e.g. a tracker reply
+    if command == "dump" && file_name == "-" {
+        let mut stdin = Decoder::new(io::stdin().lock());
+        let value = match stdin.next() {
+            Ok(value) => value.unwrap_or_default(),
+            Err(err) => {
+                eprintln!("{}: {}", file_name, err);
+                process::exit(1);
+            }
+        };
+        let value =
+            bencode::from_bytes(value).unwrap_or_else(|err| decode_error(file_name, value, err));
+        return dump(file_name, &value, path);
+    }
+
+    let dht = match Dht::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT))).await {
+        Ok(dht) => Some(dht),
+        Err(err) => {
+            eprintln!("dht: {}", err);
+            None
+        }
+    };
+
+    if let Some(dht) = &dht
+        && let Ok(file) = tokio::fs::File::open(DHT_STATE_FILE).await
+    {
+        let mut state = Decoder::new(file);
+        let result = match state.next_async().await {
+            Ok(Some(state)) => dht.load(state).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            eprintln!("{}: {}", DHT_STATE_FILE, err);
         }
+    }
+
+    let contents = match &magnet {
+        Some(magnet) => fetch_metadata(magnet, &peer_id, dht.as_ref()).await,
+        None => match fs::read(file_name) {
+            Ok(contents) => contents,
+            Err(message) => {
+                eprintln!("{}: {}", file_name, message);
+                process::exit(1);
+            }
+        },
     };
 
-    let torrent = match bencode::from_bytes::<Metainfo>(&contents) {
-        Ok(torrent) => torrent,
+    if command == "dump" {
+        if let Some(dht) = &dht {
+            save_dht(dht).await;
+        }
+
+        // The metadata of a magnet link is only the info dictionary
+        let value = match &magnet {
+            Some(magnet) => bencode::to_value(&magnet.metainfo(&contents)?)?,
+            None => bencode::from_bytes(&contents)
+                .unwrap_or_else(|err| decode_error(file_name, &contents, err)),
+        };
+        return dump(file_name, &value, path);
+    }
+
+    let torrent = match &magnet {
+        Some(magnet) => magnet.metainfo(&contents),
+        None => bencode::from_bytes::<Metainfo>(&contents),
+    };
+
+    let torrent = torrent.unwrap_or_else(|err| decode_error(file_name, &contents, err));
+
+    let metadata = torrent.info.bytes().to_vec();
+
+    let storage = match Storage::new(&torrent.info, Path::new(".")) {
+        Ok(storage) => storage,
         Err(err) => {
             eprintln!("{}: {}", file_name, err);
             process::exit(1);
         }
     };
 
-    let mut tracker = Tracker::new(torrent.announce.clone());
+    let trackers = Trackers::new(
+        torrent.announce.as_deref().unwrap_or_default(),
+        &torrent.announce_list,
+    );
 
-    let mut download = Download::new(&torrent);
+    let mut download = Download::new(metadata, peer_id, &torrent.info, storage);
+    if let Some(magnet) = &magnet {
+        download.add_peers(magnet.peers.iter().cloned());
+    }
+    let download = Arc::new(Mutex::new(download));
+    download::resume(&download).await;
 
-    tracker.started(&mut download).await?;
+    let (stop, stopped) = oneshot::channel();
+    let (warnings, mut tracker_warnings) = mpsc::unbounded_channel();
+    let announcer = tokio::spawn(trackers.run(download.clone(), stopped, warnings));
+    tokio::spawn(async move {
+        while let Some((tracker, warning)) = tracker_warnings.recv().await {
+            eprintln!("{}: warning: {}", tracker, warning);
+        }
+    });
+    let swarm = tokio::spawn(peer::swarm(download.clone()));
+    let web_seeds: Vec<_> = torrent
+        .url_list
+        .iter()
+        .flatten()
+        .map(|url| tokio::spawn(WebSeed::new(url, &torrent.info).run(download.clone())))
+        .collect();
+
+    // Private torrents only use their trackers
+    let private = torrent.info.is_private();
+
+    let mut lsd_tasks = Vec::new();
+    for group in [lsd::GROUP_V4, lsd::GROUP_V6]
+        .into_iter()
+        .filter(|_| !private)
+    {
+        match Lsd::bind(group).await {
+            Ok(lsd) => lsd_tasks.push(tokio::spawn(lsd.run(download.clone()))),
+            Err(err) => eprintln!("{}: {}", group, err),
+        }
+    }
+
+    // Nodes from the torrent are tried before the well-known routers
+    let dht_nodes = torrent
+        .nodes
+        .iter()
+        .flatten()
+        .map(|(host, port)| match host.contains(':') {
+            true => format!("[{}]:{}", host, port),
+            false => format!("{}:{}", host, port),
+        })
+        .chain(DHT_BOOTSTRAP_NODES.map(String::from))
+        .collect();
+    let dht_task = dht
+        .clone()
+        .filter(|_| !private)
+        .map(|dht| tokio::spawn(dht.run(download.clone(), dht_nodes)));
+
+    shutdown().await;
+
+    swarm.abort();
+    web_seeds.iter().for_each(|task| task.abort());
+    lsd_tasks.iter().for_each(|task| task.abort());
+    if let Some(dht_task) = dht_task {
+        dht_task.abort();
+    }
+    if let Some(dht) = dht {
+        save_dht(&dht).await;
+    }
+    let _ = stop.send(());
+    announcer.await?;
 
     Ok(())
 }
 
+async fn scrape_torrent(
+    trackers: &mut Trackers,
+    info_hash: &[u8],
+) -> Result<(), Box<dyn error::Error>> {
+    let (announce, scrape) = trackers.scrape(info_hash).await?;
+
+    println!(
+        "{}: seeders: {}, leechers: {}, completed: {}",
+        announce, scrape.complete, scrape.incomplete, scrape.downloaded
+    );
+
+    Ok(())
+}
+
+// Keeps asking the trackers, the DHT and the peers from the magnet link until one
+// of the peers sends metadata matching the info hash
+async fn fetch_metadata(magnet: &Magnet, peer_id: &[u8], dht: Option<&Dht>) -> Vec<u8> {
+    let mut trackers = Trackers::new("", slice::from_ref(&magnet.trackers));
+
+    println!(
+        "fetching metadata for {}",
+        magnet.name.as_deref().unwrap_or("magnet link")
+    );
+
+    if let Some(dht) = dht {
+        dht.bootstrap(&DHT_BOOTSTRAP_NODES).await;
+    }
+
+    loop {
+        let mut peers = magnet.peers.clone();
+
+        if let Some(dht) = dht {
+            peers.extend(dht.get_peers(&magnet.info_hash).await);
+        }
+
+        match trackers.peers(&magnet.info_hash, peer_id).await {
+            Ok(announcement) => {
+                if let Some(warning) = &announcement.warning {
+                    eprintln!("{}: warning: {}", announcement.tracker, warning);
+                }
+                peers.extend(announcement.peers);
+            }
+            Err(err) => eprintln!("{}", err),
+        }
+
+        match metadata::fetch(&peers, &magnet.info_hash, peer_id).await {
+            Ok(metadata) => return metadata,
+            Err(err) => eprintln!("{}", err),
+        }
+
+        tokio::time::sleep(METADATA_RETRY_INTERVAL).await;
+    }
+}
+
+// Shows where in `contents` decoding failed
+fn decode_error(file_name: &str, contents: &[u8], err: bencode::Error) -> ! {
+    eprintln!("{}: {}", file_name, err);
+
+    if let Some(snippet) = err.snippet(contents) {
+        eprintln!("{}", snippet);
+    }
+
+    process::exit(1);
+}
+
+fn dump(
+    file_name: &str,
+    value: &Value,
+    path: Option<&String>,
+) -> Result<(), Box<dyn error::Error>> {
+    let value = match path {
+        Some(path) => value
+            .path(path)
+            .ok_or_else(|| format!("{}: {} not found", file_name, path))?,
+        None => value,
+    };
+
+    println!("{}", value);
+    Ok(())
+}
+
+// The state is only replaced once the new one is written in full
+async fn save_dht(dht: &Dht) {
+    let result = async {
+        let mut file = tokio::fs::File::create(DHT_TEMP_FILE).await?;
+        dht.save(&mut file).await?;
+        file.flush().await?;
+        tokio::fs::rename(DHT_TEMP_FILE, DHT_STATE_FILE).await
+    }
+    .await;
+
+    if let Err(err) = result {
+        eprintln!("{}: {}", DHT_STATE_FILE, err);
+    }
+}
+
+// Resolves on Ctrl-C or, on Unix, SIGTERM
+async fn shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
 fn usage() {
-    eprintln!("Usage: {} <torrent file>", PROGRAM);
+    eprintln!("Usage: {} <torrent file | magnet link>", PROGRAM);
+    eprintln!("       {} scrape <torrent file | magnet link>", PROGRAM);
+    eprintln!(
+        "       {} dump <torrent file | magnet link | -> [path]",
+        PROGRAM
+    );
 }
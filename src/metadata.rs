@@ -0,0 +1,311 @@
+use crate::bencode::{self, Partial};
+use crate::extension::{self, Extension, Registry};
+use crate::metainfo::sha1;
+use crate::peer::{Connection, Message};
+use crate::tracker::Peer;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_FETCHES: usize = 30;
+const MAX_METADATA_SIZE: i64 = 1 << 24;
+const METADATA_PIECE_SIZE: i64 = 1 << 14;
+const MSG_TYPE_DATA: i64 = 1;
+const MSG_TYPE_REJECT: i64 = 2;
+const MSG_TYPE_REQUEST: i64 = 0;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MetadataMessage {
+    msg_type: i64,
+    piece: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    total_size: i64,
+}
+
+// Fetches the info dictionary (BEP 9) from the first of `peers` able to send a
+// copy that matches `info_hash`
+pub async fn fetch(peers: &[Peer], info_hash: &[u8], peer_id: &[u8]) -> io::Result<Vec<u8>> {
+    let mut fetches = JoinSet::new();
+
+    for peer in peers.iter().take(MAX_FETCHES) {
+        let addr = peer.addr;
+        let info_hash = info_hash.to_vec();
+        let peer_id = peer_id.to_vec();
+
+        fetches.spawn(async move {
+            let result =
+                tokio::time::timeout(FETCH_TIMEOUT, fetch_from(addr, &info_hash, &peer_id))
+                    .await
+                    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+            (addr, result)
+        });
+    }
+
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "No peers");
+
+    while let Some(Ok((addr, result))) = fetches.join_next().await {
+        match result {
+            Ok(metadata) => return Ok(metadata),
+            Err(err) => {
+                eprintln!("{}: {}", addr, err);
+                error = err;
+            }
+        }
+    }
+
+    Err(error)
+}
+
+async fn fetch_from(addr: SocketAddr, info_hash: &[u8], peer_id: &[u8]) -> io::Result<Vec<u8>> {
+    let mut connection = Connection::connect(addr, info_hash, peer_id).await?;
+
+    if !connection.handshake.supports_extensions() {
+        return Err(invalid_data("Peer does not support extensions"));
+    }
+
+    let mut registry = Registry::new(&[Extension::Metadata]);
+
+    connection
+        .send(&Message::Extended {
+            id: extension::HANDSHAKE_ID,
+            payload: registry
+                .handshake(addr.ip(), 0)
+                .to_bytes()
+                .map_err(bencode_error)?,
+        })
+        .await?;
+
+    let handshake = loop {
+        if let Message::Extended {
+            id: extension::HANDSHAKE_ID,
+            payload,
+        } = connection.receive().await?
+        {
+            break extension::Handshake::from_bytes(&payload).map_err(bencode_error)?;
+        }
+    };
+
+    registry.negotiate(&handshake);
+
+    let id = registry
+        .id(Extension::Metadata)
+        .ok_or_else(|| invalid_data("Peer does not support ut_metadata"))?;
+
+    let size = handshake.metadata_size;
+
+    if size <= 0 || size > MAX_METADATA_SIZE {
+        return Err(invalid_data("Invalid metadata size"));
+    }
+
+    let mut metadata = Vec::with_capacity(size as usize);
+
+    // Pieces are requested one at a time, metadata is small enough for this not to matter
+    let pieces = (size as u64).div_ceil(METADATA_PIECE_SIZE as u64) as i64;
+
+    for piece in 0..pieces {
+        let request = MetadataMessage {
+            msg_type: MSG_TYPE_REQUEST,
+            piece,
+            total_size: 0,
+        };
+
+        connection
+            .send(&Message::Extended {
+                id,
+                payload: bencode::to_bytes(&request).map_err(bencode_error)?,
+            })
+            .await?;
+
+        loop {
+            let payload = match connection.receive().await? {
+                Message::Extended { id, payload }
+                    if registry.route(id) == Some(Extension::Metadata) =>
+                {
+                    payload
+                }
+                _ => continue,
+            };
+
+            let (message, len) = match bencode::from_bytes_partial::<MetadataMessage>(&payload)
+                .map_err(bencode_error)?
+            {
+                Partial::Complete(message, len) => (message, len),
+                Partial::Incomplete => return Err(invalid_data("Truncated metadata message")),
+            };
+
+            match message.msg_type {
+                MSG_TYPE_DATA if message.piece == piece => {
+                    let data = &payload[len..];
+                    let expected = METADATA_PIECE_SIZE.min(size - piece * METADATA_PIECE_SIZE);
+
+                    if data.len() as i64 != expected {
+                        return Err(invalid_data("Invalid metadata piece length"));
+                    }
+
+                    metadata.extend_from_slice(data);
+                    break;
+                }
+                MSG_TYPE_REJECT if message.piece == piece => {
+                    return Err(invalid_data("Metadata request rejected"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if sha1(&metadata) != info_hash {
+        return Err(invalid_data("Metadata does not match info hash"));
+    }
+
+    Ok(metadata)
+}
+
+// Answers a ut_metadata message from a peer, returning the payload of the reply.
+// Requests for pieces we do not have are rejected.
+pub fn respond(metadata: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+    let Ok(Partial::Complete(request, _)) = bencode::from_bytes_partial::<MetadataMessage>(payload)
+    else {
+        return None;
+    };
+
+    if request.msg_type != MSG_TYPE_REQUEST {
+        return None;
+    }
+
+    let begin = request.piece.checked_mul(METADATA_PIECE_SIZE)?;
+
+    let (message, data) = if request.piece >= 0 && begin < metadata.len() as i64 {
+        let end = (begin + METADATA_PIECE_SIZE).min(metadata.len() as i64);
+        let message = MetadataMessage {
+            msg_type: MSG_TYPE_DATA,
+            piece: request.piece,
+            total_size: metadata.len() as i64,
+        };
+        (message, &metadata[begin as usize..end as usize])
+    } else {
+        let message = MetadataMessage {
+            msg_type: MSG_TYPE_REJECT,
+            piece: request.piece,
+            total_size: 0,
+        };
+        (message, &[][..])
+    };
+
+    let mut reply = Vec::with_capacity(bencode::serialized_size(&message).ok()? + data.len());
+    bencode::to_writer(&mut reply, &message).ok()?;
+    reply.extend_from_slice(data);
+    Some(reply)
+}
+
+fn bencode_error(err: bencode::Error) -> io::Error {
+    invalid_data(&err.to_string())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn is_zero(n: &i64) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fetch, respond};
+    use crate::extension;
+    use crate::metainfo::sha1;
+    use crate::peer::{Handshake, Message};
+    use crate::tracker::Peer;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    // A peer that has `metadata` and expects ut_metadata messages under ID 2
+    async fn seeder(metadata: Vec<u8>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let handshake = Handshake::read(&mut stream).await.unwrap();
+            Handshake::new(&handshake.info_hash, &[3; 20])
+                .write(&mut stream)
+                .await
+                .unwrap();
+
+            let mut ours = extension::Handshake::default();
+            ours.m.insert(String::from("ut_metadata"), 2);
+            ours.metadata_size = metadata.len() as i64;
+            let mut id = 0;
+
+            while let Ok(message) = Message::read(&mut stream).await {
+                let reply = match message {
+                    Message::Extended { id: 0, payload } => {
+                        let theirs = extension::Handshake::from_bytes(&payload).unwrap();
+                        id = theirs.m["ut_metadata"] as u8;
+
+                        Message::Extended {
+                            id: extension::HANDSHAKE_ID,
+                            payload: ours.to_bytes().unwrap(),
+                        }
+                    }
+                    Message::Extended { id: 2, payload } => Message::Extended {
+                        id,
+                        payload: respond(&metadata, &payload).unwrap(),
+                    },
+                    _ => continue,
+                };
+
+                reply.write(&mut stream).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    fn peer(addr: SocketAddr) -> Peer {
+        Peer {
+            addr,
+            peer_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let metadata: Vec<u8> = (0..20000).map(|n| n as u8).collect();
+        let addr = seeder(metadata.clone()).await;
+
+        let fetched = fetch(&[peer(addr)], &sha1(&metadata), &[1; 20])
+            .await
+            .unwrap();
+
+        assert_eq!(fetched, metadata);
+    }
+
+    #[tokio::test]
+    async fn test_hash_mismatch() {
+        let addr = seeder(vec![1; 100]).await;
+
+        assert!(
+            fetch(&[peer(addr)], &sha1(&[2; 100]), &[1; 20])
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_respond() {
+        let metadata = vec![7; 20000];
+
+        let reply = respond(&metadata, b"d8:msg_typei0e5:piecei1ee").unwrap();
+        assert!(reply.starts_with(b"d8:msg_typei1e5:piecei1e10:total_sizei20000ee"));
+        assert_eq!(reply.len(), 45 + 20000 - (1 << 14));
+
+        let reply = respond(&metadata, b"d8:msg_typei0e5:piecei2ee").unwrap();
+        assert_eq!(reply, b"d8:msg_typei2e5:piecei2ee");
+
+        assert_eq!(respond(&metadata, b"d8:msg_typei2e5:piecei0ee"), None);
+    }
+}
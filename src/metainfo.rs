@@ -1,14 +1,20 @@
-use serde::{Deserialize, Serialize};
+use crate::bencode::{self, Spanned, Value};
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize};
 use sha1::{Digest, Sha1};
 
+const HASH_LENGTH: usize = 20;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct File {
-    length: i64,
-    path: Vec<String>,
+    pub length: i64,
+    pub path: Vec<String>,
 }
 
+// Decoded through RawInfo rather than as an untagged enum, which would hide where
+// in the dictionary an error is
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "RawInfo")]
 pub enum Info<'a> {
     Single {
         length: i64,
@@ -18,6 +24,8 @@ pub enum Info<'a> {
         #[serde(borrow)]
         #[serde(with = "serde_bytes")]
         pieces: &'a [u8],
+        private: Option<i64>,
+        source: Option<String>,
     },
     Multi {
         files: Vec<File>,
@@ -27,31 +35,451 @@ pub enum Info<'a> {
         #[serde(borrow)]
         #[serde(with = "serde_bytes")]
         pieces: &'a [u8],
+        private: Option<i64>,
+        source: Option<String>,
     },
 }
 
+#[derive(Deserialize)]
+struct RawInfo<'a> {
+    files: Option<Vec<File>>,
+    length: Option<i64>,
+    name: String,
+    #[serde(rename = "piece length")]
+    piece_length: i64,
+    #[serde(borrow)]
+    #[serde(with = "serde_bytes")]
+    pieces: &'a [u8],
+    private: Option<i64>,
+    source: Option<String>,
+}
+
+impl<'a> TryFrom<RawInfo<'a>> for Info<'a> {
+    type Error = &'static str;
+
+    // Single-file torrents have `length`, multi-file torrents `files`
+    fn try_from(raw: RawInfo<'a>) -> Result<Self, Self::Error> {
+        let info = match (raw.length, raw.files) {
+            (Some(length), _) => Ok(Info::Single {
+                length,
+                name: raw.name,
+                piece_length: raw.piece_length,
+                pieces: raw.pieces,
+                private: raw.private,
+                source: raw.source,
+            }),
+            (None, Some(files)) => Ok(Info::Multi {
+                files,
+                name: raw.name,
+                piece_length: raw.piece_length,
+                pieces: raw.pieces,
+                private: raw.private,
+                source: raw.source,
+            }),
+            (None, None) => Err("Expected length or files"),
+        }?;
+
+        info.validate()?;
+        Ok(info)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSpan<'a> {
+    pub length: i64,
+    pub offset: i64,
+    pub path: &'a [String],
+}
+
+impl<'a> Info<'a> {
+    // Every piece has a hash and every hash a piece, which also keeps the sizes
+    // computed from them in range
+    fn validate(&self) -> Result<(), &'static str> {
+        let piece_length = self.piece_length();
+
+        if piece_length <= 0 || piece_length > u32::MAX as i64 {
+            return Err("Invalid piece length");
+        }
+
+        if !self.pieces().len().is_multiple_of(HASH_LENGTH) {
+            return Err("Pieces is not a multiple of 20 bytes");
+        }
+
+        let length = self
+            .files()
+            .iter()
+            .try_fold(0i64, |total, file| match file.length {
+                0.. => total.checked_add(file.length),
+                _ => None,
+            })
+            .ok_or("Invalid length")?;
+
+        if (length as u64).div_ceil(piece_length as u64) != self.piece_count() as u64 {
+            return Err("Number of pieces does not match length");
+        }
+
+        Ok(())
+    }
+
+    // Files with their offset in the torrent's byte space. For single-file torrents
+    // the path is empty and the file is `name` itself.
+    pub fn files(&self) -> Vec<FileSpan<'_>> {
+        match self {
+            Info::Single { length, .. } => vec![FileSpan {
+                length: *length,
+                offset: 0,
+                path: &[],
+            }],
+            Info::Multi { files, .. } => {
+                let mut offset = 0;
+                files
+                    .iter()
+                    .map(|file| {
+                        let span = FileSpan {
+                            length: file.length,
+                            offset,
+                            path: &file.path,
+                        };
+                        offset += file.length;
+                        span
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn last_piece_length(&self) -> i64 {
+        match self.piece_count() {
+            0 => 0,
+            count => (self.length() - (count as i64 - 1) * self.piece_length())
+                .clamp(0, self.piece_length()),
+        }
+    }
+
+    pub fn length(&self) -> i64 {
+        match self {
+            Info::Single { length, .. } => *length,
+            Info::Multi { files, .. } => files.iter().map(|file| file.length).sum(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Info::Single { name, .. } | Info::Multi { name, .. } => name,
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces().len() / HASH_LENGTH
+    }
+
+    pub fn piece_hash(&self, index: usize) -> &'a [u8] {
+        &self.pieces()[index * HASH_LENGTH..(index + 1) * HASH_LENGTH]
+    }
+
+    pub fn piece_length(&self) -> i64 {
+        match self {
+            Info::Single { piece_length, .. } | Info::Multi { piece_length, .. } => *piece_length,
+        }
+    }
+
+    // Size of the piece at `index`, which is `piece_length` for every piece but the last
+    pub fn piece_size(&self, index: usize) -> i64 {
+        match self.piece_count() {
+            count if index + 1 == count => self.last_piece_length(),
+            count if index < count => self.piece_length(),
+            _ => 0,
+        }
+    }
+
+    // Private torrents (BEP 27) only get peers from their trackers
+    pub fn is_private(&self) -> bool {
+        match self {
+            Info::Single { private, .. } | Info::Multi { private, .. } => *private == Some(1),
+        }
+    }
+
+    pub fn pieces(&self) -> &'a [u8] {
+        match self {
+            Info::Single { pieces, .. } | Info::Multi { pieces, .. } => pieces,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Metainfo<'a> {
-    pub announce: String,
-    comment: String,
+    // Trackerless torrents only have DHT nodes
+    pub announce: Option<String>,
+    #[serde(
+        default,
+        rename = "announce-list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub announce_list: Vec<Vec<String>>,
+    comment: Option<String>,
     #[serde(rename = "created by")]
-    created_by: String,
+    created_by: Option<String>,
     #[serde(rename = "creation date")]
-    creation_date: i64,
+    creation_date: Option<i64>,
+    encoding: Option<String>,
+    // Hoffman-style web seeds (BEP 17), not supported
+    httpseeds: Option<Vec<String>>,
     #[serde(borrow)]
-    pub info: Info<'a>,
-    #[serde(rename = "url-list")]
-    url_list: Vec<String>,
+    pub info: Spanned<'a, Info<'a>>,
+    // DHT nodes as host and port pairs (BEP 5)
+    pub nodes: Option<Vec<(String, u16)>>,
+    #[serde(default, rename = "url-list", deserialize_with = "one_or_many")]
+    pub url_list: Option<Vec<String>>,
 }
 
-impl Metainfo<'_> {
-    pub fn info_hash(&self) -> Result<Vec<u8>, crate::bencode::Error> {
-        Ok(sha1(&crate::bencode::to_bytes(&self.info)?))
+impl<'a> Metainfo<'a> {
+    pub fn new(
+        announce: Option<String>,
+        announce_list: Vec<Vec<String>>,
+        info: Spanned<'a, Info<'a>>,
+    ) -> Self {
+        Self {
+            announce,
+            announce_list,
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            encoding: None,
+            httpseeds: None,
+            info,
+            nodes: None,
+            url_list: None,
+        }
+    }
+
+    // Hash of the info dictionary exactly as it was encoded
+    pub fn info_hash(&self) -> Vec<u8> {
+        sha1(self.info.bytes())
     }
 }
 
-fn sha1(bytes: &[u8]) -> Vec<u8> {
+// `url-list` is either a single URL or a list of them
+fn one_or_many<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<Value>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    let urls = match value {
+        Value::Bytes(_) => vec![bencode::from_value(&value).map_err(de::Error::custom)?],
+        _ => bencode::from_value(&value).map_err(de::Error::custom)?,
+    };
+    Ok(Some(urls))
+}
+
+pub fn sha1(bytes: &[u8]) -> Vec<u8> {
     let mut hasher = Sha1::new();
     hasher.update(bytes);
     hasher.finalize().into_iter().collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{File, FileSpan, Info, Metainfo, sha1};
+    use crate::bencode;
+
+    fn single(length: i64, piece_length: i64, pieces: &[u8]) -> Info<'_> {
+        Info::Single {
+            length,
+            name: String::from("file"),
+            piece_length,
+            pieces,
+            private: None,
+            source: None,
+        }
+    }
+
+    fn multi<'a>(lengths: &[i64], piece_length: i64, pieces: &'a [u8]) -> Info<'a> {
+        Info::Multi {
+            files: lengths
+                .iter()
+                .enumerate()
+                .map(|(index, length)| File {
+                    length: *length,
+                    path: vec![index.to_string()],
+                })
+                .collect(),
+            name: String::from("dir"),
+            piece_length,
+            pieces,
+            private: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_odd_last_piece() {
+        let pieces = [0u8; 80];
+        let info = single(50, 16, &pieces);
+
+        assert_eq!(info.length(), 50);
+        assert_eq!(info.piece_count(), 4);
+        assert_eq!(info.piece_size(0), 16);
+        assert_eq!(info.piece_size(2), 16);
+        assert_eq!(info.piece_size(3), 2);
+        assert_eq!(info.last_piece_length(), 2);
+
+        let info = single(35, 16, &pieces[..60]);
+
+        assert_eq!(info.piece_size(1), 16);
+        assert_eq!(info.last_piece_length(), 3);
+    }
+
+    #[test]
+    fn test_exact_last_piece() {
+        let pieces = [0u8; 40];
+        let info = single(32, 16, &pieces);
+
+        assert_eq!(info.piece_count(), 2);
+        assert_eq!(info.last_piece_length(), 16);
+    }
+
+    #[test]
+    fn test_empty() {
+        let info = single(0, 16, &[]);
+
+        assert_eq!(info.piece_count(), 0);
+        assert_eq!(info.last_piece_length(), 0);
+    }
+
+    #[test]
+    fn test_multi_length() {
+        let pieces = [0u8; 40];
+        let info = multi(&[10, 0, 7, 0], 16, &pieces);
+
+        assert_eq!(info.length(), 17);
+        assert_eq!(info.piece_count(), 2);
+        assert_eq!(info.last_piece_length(), 1);
+    }
+
+    #[test]
+    fn test_zero_length_files() {
+        let info = multi(&[0, 10, 0, 7, 0], 16, &[]);
+        let offsets: Vec<(i64, i64)> = info
+            .files()
+            .iter()
+            .map(|file| (file.offset, file.length))
+            .collect();
+
+        assert_eq!(offsets, [(0, 0), (0, 10), (10, 0), (10, 7), (17, 0)]);
+    }
+
+    #[test]
+    fn test_single_files() {
+        let info = single(5, 16, &[]);
+
+        assert_eq!(
+            info.files(),
+            [FileSpan {
+                length: 5,
+                offset: 0,
+                path: &[],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_piece_hash() {
+        let pieces: Vec<u8> = (0..40).collect();
+        let info = single(20, 16, &pieces);
+
+        assert_eq!(info.piece_hash(1), &pieces[20..]);
+    }
+
+    #[test]
+    fn test_invalid_info() {
+        let input = b"d4:infod4:name1:x12:piece lengthi1e6:pieces0:ee";
+        let err = bencode::from_bytes::<Metainfo>(input).unwrap_err();
+
+        assert_eq!(err.to_string(), "Expected length or files: info at byte 46");
+
+        let input = b"d4:infod5:filesld6:lengthi1e4:pathl1:aeed4:pathl1:beee4:name1:x12:piece lengthi1e6:pieces0:ee";
+        let err = bencode::from_bytes::<Metainfo>(input).unwrap_err();
+
+        assert_eq!(err.path, "info.files[1]");
+    }
+
+    #[test]
+    fn test_invalid_pieces() {
+        let invalid = [
+            // Negative, zero and oversized piece lengths
+            &b"d6:lengthi5e4:name1:x12:piece lengthi-1e6:pieces0:e"[..],
+            b"d6:lengthi5e4:name1:x12:piece lengthi0e6:pieces0:e",
+            b"d6:lengthi5e4:name1:x12:piece lengthi4294967296e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+            // A partial hash, too few and too many pieces
+            b"d6:lengthi5e4:name1:x12:piece lengthi16e6:pieces21:aaaaaaaaaaaaaaaaaaaaae",
+            b"d6:lengthi17e4:name1:x12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+            b"d6:lengthi0e4:name1:x12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae",
+            // Negative lengths
+            b"d6:lengthi-5e4:name1:x12:piece lengthi16e6:pieces0:e",
+            b"d5:filesld6:lengthi-1e4:pathl1:aeee4:name1:x12:piece lengthi16e6:pieces0:e",
+        ];
+
+        for input in invalid {
+            assert!(bencode::from_bytes::<Info>(input).is_err());
+        }
+
+        let valid = b"d6:lengthi17e4:name1:x12:piece lengthi16e6:pieces40:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaae";
+        assert_eq!(
+            bencode::from_bytes::<Info>(valid)
+                .unwrap()
+                .last_piece_length(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_optional_keys() {
+        let input =
+            b"d4:infod6:lengthi5e4:name4:file12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let torrent = bencode::from_bytes::<Metainfo>(input).unwrap();
+
+        assert_eq!(torrent.announce, None);
+        assert_eq!(torrent.comment, None);
+        assert_eq!(torrent.url_list, None);
+        assert!(!torrent.info.is_private());
+        assert_eq!(bencode::to_bytes(&torrent).unwrap(), input);
+    }
+
+    #[test]
+    fn test_standard_keys() {
+        let input = b"d8:announce9:http://a/7:comment1:c10:created by1:b13:creation datei1e8:encoding5:UTF-89:httpseedsl9:http://h/e4:infod6:lengthi5e4:name4:file12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:privatei1e6:source1:se5:nodesll1:ai6881eee8:url-list9:http://u/e";
+        let torrent = bencode::from_bytes::<Metainfo>(input).unwrap();
+
+        assert_eq!(torrent.announce.as_deref(), Some("http://a/"));
+        assert_eq!(torrent.creation_date, Some(1));
+        assert_eq!(torrent.encoding.as_deref(), Some("UTF-8"));
+        assert_eq!(torrent.nodes, Some(vec![(String::from("a"), 6881)]));
+        assert_eq!(torrent.url_list, Some(vec![String::from("http://u/")]));
+        assert!(torrent.info.is_private());
+
+        // A single web seed is written back as a list
+        let output = bencode::to_bytes(&torrent).unwrap();
+        assert!(output.ends_with(b"8:url-listl9:http://u/ee"));
+        assert_eq!(&output[..output.len() - 24], &input[..input.len() - 22]);
+    }
+
+    #[test]
+    fn test_info_hash() {
+        let info = b"d6:lengthi5e6:md5sum32:0123456789abcdef0123456789abcdef4:name4:file12:piece lengthi16e6:pieces20:aaaaaaaaaaaaaaaaaaaae";
+        let mut input = b"d4:info".to_vec();
+        input.extend_from_slice(info);
+        input.push(b'e');
+
+        let torrent = bencode::from_bytes::<Metainfo>(&input).unwrap();
+
+        // md5sum is not part of Info and would be lost by encoding it again
+        assert_eq!(torrent.info_hash(), sha1(info));
+        assert_ne!(
+            torrent.info_hash(),
+            sha1(&bencode::to_bytes(&*torrent.info).unwrap())
+        );
+    }
+}
@@ -0,0 +1,846 @@
+use crate::download::{self, Download};
+use crate::extension::{self, Extension, Registry};
+use crate::metadata;
+use crate::pex::{self, Pex};
+use crate::piece::{Bitfield, Block, Completion};
+use crate::tracker::Peer;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{Mutex, broadcast, mpsc};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Reserved bit announcing support for the extension protocol (BEP 10)
+const EXTENSION_BIT: (usize, u8) = (5, 0x10);
+const HANDSHAKE_LENGTH: usize = 49 + PROTOCOL.len();
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(90);
+const MAX_CONNECTIONS: usize = 50;
+const MAX_MESSAGE_LENGTH: usize = 1 << 21;
+const MAX_REQUEST_LENGTH: u32 = 1 << 17;
+const PIPELINE_LENGTH: usize = 5;
+const PROTOCOL: &[u8] = b"BitTorrent protocol";
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(120);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handshake {
+    pub info_hash: Vec<u8>,
+    pub peer_id: Vec<u8>,
+    pub reserved: [u8; 8],
+}
+
+impl Handshake {
+    pub fn new(info_hash: &[u8], peer_id: &[u8]) -> Self {
+        let mut reserved = [0; 8];
+        reserved[EXTENSION_BIT.0] |= EXTENSION_BIT.1;
+
+        Self {
+            info_hash: info_hash.to_vec(),
+            peer_id: peer_id.to_vec(),
+            reserved,
+        }
+    }
+
+    pub fn supports_extensions(&self) -> bool {
+        self.reserved[EXTENSION_BIT.0] & EXTENSION_BIT.1 != 0
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HANDSHAKE_LENGTH);
+        bytes.push(PROTOCOL.len() as u8);
+        bytes.extend_from_slice(PROTOCOL);
+        bytes.extend_from_slice(&self.reserved);
+        bytes.extend_from_slice(&self.info_hash);
+        bytes.extend_from_slice(&self.peer_id);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != HANDSHAKE_LENGTH
+            || bytes[0] as usize != PROTOCOL.len()
+            || &bytes[1..20] != PROTOCOL
+        {
+            return Err(invalid_data("Invalid handshake"));
+        }
+
+        Ok(Self {
+            info_hash: bytes[28..48].to_vec(),
+            peer_id: bytes[48..68].to_vec(),
+            reserved: bytes[20..28].try_into().unwrap(),
+        })
+    }
+
+    pub async fn read<R>(reader: &mut R) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut bytes = [0u8; HANDSHAKE_LENGTH];
+        reader.read_exact(&mut bytes).await?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub async fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(&self.to_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have {
+        index: u32,
+    },
+    Bitfield {
+        bitfield: Vec<u8>,
+    },
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Extended {
+        id: u8,
+        payload: Vec<u8>,
+    },
+    // Messages of extensions we don't support, e.g. PORT (BEP 5) or the fast
+    // extension (BEP 6). The length prefix lets them be skipped.
+    Unknown {
+        id: u8,
+        payload: Vec<u8>,
+    },
+}
+
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        match self {
+            Message::KeepAlive => {}
+            Message::Choke => payload.push(0),
+            Message::Unchoke => payload.push(1),
+            Message::Interested => payload.push(2),
+            Message::NotInterested => payload.push(3),
+            Message::Have { index } => {
+                payload.push(4);
+                payload.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::Bitfield { bitfield } => {
+                payload.push(5);
+                payload.extend_from_slice(bitfield);
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                payload.push(6);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                payload.push(7);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(block);
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => {
+                payload.push(8);
+                payload.extend_from_slice(&index.to_be_bytes());
+                payload.extend_from_slice(&begin.to_be_bytes());
+                payload.extend_from_slice(&length.to_be_bytes());
+            }
+            Message::Extended { id, payload: data } => {
+                payload.push(20);
+                payload.push(*id);
+                payload.extend_from_slice(data);
+            }
+            Message::Unknown { id, payload: data } => {
+                payload.push(*id);
+                payload.extend_from_slice(data);
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(4 + payload.len());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    // Parses a message payload, i.e. everything after the length prefix
+    pub fn from_bytes(payload: &[u8]) -> io::Result<Self> {
+        let (id, body) = match payload.split_first() {
+            Some((id, body)) => (*id, body),
+            None => return Ok(Message::KeepAlive),
+        };
+
+        let message = match (id, body.len()) {
+            (0, 0) => Message::Choke,
+            (1, 0) => Message::Unchoke,
+            (2, 0) => Message::Interested,
+            (3, 0) => Message::NotInterested,
+            (4, 4) => Message::Have {
+                index: read_u32(body, 0),
+            },
+            (5, _) => Message::Bitfield {
+                bitfield: body.to_vec(),
+            },
+            (6, 12) => Message::Request {
+                index: read_u32(body, 0),
+                begin: read_u32(body, 4),
+                length: read_u32(body, 8),
+            },
+            (7, 8..) => Message::Piece {
+                index: read_u32(body, 0),
+                begin: read_u32(body, 4),
+                block: body[8..].to_vec(),
+            },
+            (8, 12) => Message::Cancel {
+                index: read_u32(body, 0),
+                begin: read_u32(body, 4),
+                length: read_u32(body, 8),
+            },
+            (20, 1..) => Message::Extended {
+                id: body[0],
+                payload: body[1..].to_vec(),
+            },
+            (0..=8 | 20, _) => return Err(invalid_data("Invalid message length")),
+            _ => Message::Unknown {
+                id,
+                payload: body.to_vec(),
+            },
+        };
+
+        Ok(message)
+    }
+
+    pub async fn read<R>(reader: &mut R) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let length = reader.read_u32().await? as usize;
+
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(invalid_data("Message too long"));
+        }
+
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload).await?;
+
+        Self::from_bytes(&payload)
+    }
+
+    pub async fn write<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(&self.to_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+pub struct Connection {
+    pub addr: SocketAddr,
+    pub handshake: Handshake,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    pub async fn connect<A>(addr: A, info_hash: &[u8], peer_id: &[u8]) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let stream = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(stream) => stream?,
+            Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+        };
+
+        let addr = stream.peer_addr()?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        Handshake::new(info_hash, peer_id)
+            .write(&mut writer)
+            .await?;
+
+        let handshake = Handshake::read(&mut reader).await?;
+
+        if handshake.info_hash != info_hash {
+            return Err(invalid_data("Info hash mismatch"));
+        }
+
+        Ok(Self {
+            addr,
+            handshake,
+            reader,
+            writer,
+        })
+    }
+
+    pub async fn receive(&mut self) -> io::Result<Message> {
+        Message::read(&mut self.reader).await
+    }
+
+    pub async fn send(&mut self, message: &Message) -> io::Result<()> {
+        message.write(&mut self.writer).await
+    }
+}
+
+// Keeps up to MAX_CONNECTIONS sessions running with the peers in `Download::peers`,
+// connecting to new peers as they are added and retrying old ones now and then
+pub async fn swarm(download: Arc<Mutex<Download>>) {
+    let peers_changed = download.lock().await.peers_changed.clone();
+    let mut active = HashSet::new();
+    let mut attempts: HashMap<SocketAddr, Instant> = HashMap::new();
+    let mut sessions = JoinSet::new();
+
+    loop {
+        let peers: Vec<Peer> = {
+            let download = download.lock().await;
+            download
+                .peers
+                .iter()
+                .filter(|peer| {
+                    !active.contains(&peer.addr)
+                        && attempts
+                            .get(&peer.addr)
+                            .is_none_or(|attempt| attempt.elapsed() >= RECONNECT_INTERVAL)
+                })
+                .take(MAX_CONNECTIONS.saturating_sub(active.len()))
+                .cloned()
+                .collect()
+        };
+
+        for peer in peers {
+            active.insert(peer.addr);
+            attempts.insert(peer.addr, Instant::now());
+
+            let download = download.clone();
+
+            sessions.spawn(async move {
+                let addr = peer.addr;
+
+                if let Err(err) = run(peer, download).await {
+                    eprintln!("{}: {}", addr, err);
+                }
+
+                addr
+            });
+        }
+
+        tokio::select! {
+            _ = peers_changed.notified() => {}
+            Some(Ok(addr)) = sessions.join_next() => {
+                active.remove(&addr);
+            }
+            _ = tokio::time::sleep(RECONNECT_INTERVAL) => {}
+        }
+    }
+}
+
+pub async fn run(peer: Peer, download: Arc<Mutex<Download>>) -> io::Result<()> {
+    let (info_hash, peer_id, haves) = {
+        let download = download.lock().await;
+        (
+            download.info_hash.clone(),
+            download.peer_id.clone(),
+            download.haves.subscribe(),
+        )
+    };
+
+    let connection = Connection::connect(peer.addr, &info_hash, &peer_id).await?;
+
+    if let Some(expected) = &peer.peer_id
+        && *expected != connection.handshake.peer_id
+    {
+        return Err(invalid_data("Peer id mismatch"));
+    }
+
+    download.lock().await.connected.insert(peer.addr);
+
+    let mut pending = Vec::new();
+
+    let result = exchange(connection, &download, haves, &mut pending).await;
+
+    let mut download = download.lock().await;
+    download.connected.remove(&peer.addr);
+    for block in pending {
+        download.pieces.release(block);
+    }
+
+    result
+}
+
+async fn exchange(
+    connection: Connection,
+    download: &Mutex<Download>,
+    mut haves: broadcast::Receiver<u32>,
+    pending: &mut Vec<Block>,
+) -> io::Result<()> {
+    let Connection {
+        addr,
+        handshake,
+        mut reader,
+        mut writer,
+    } = connection;
+
+    // Reading a message is not cancel safe, so it happens in its own task and the
+    // messages are handed over through a channel that can be used in select!
+    let (sender, mut messages) = mpsc::channel(16);
+    let mut tasks = JoinSet::new();
+    tasks.spawn(async move {
+        loop {
+            let message = Message::read(&mut reader).await;
+            let failed = message.is_err();
+
+            if sender.send(message).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let (len, bitfield, mut interested, metadata_size, private) = {
+        let download = download.lock().await;
+        (
+            download.pieces.len(),
+            download.pieces.bitfield(),
+            !download.pieces.is_complete(),
+            download.metadata.len() as i64,
+            download.private,
+        )
+    };
+    let mut available = Bitfield::new(len);
+    let mut choked = true;
+    let mut choking = true;
+    let mut deadline = Instant::now() + RECEIVE_TIMEOUT;
+    let mut keep_alive =
+        tokio::time::interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+    let mut pex = Pex::new();
+    let mut pex_interval = tokio::time::interval_at(Instant::now() + pex::INTERVAL, pex::INTERVAL);
+    let mut pipeline_length = PIPELINE_LENGTH;
+    let mut registry = if private {
+        Registry::new(&[Extension::Metadata])
+    } else {
+        Registry::new(&[Extension::Metadata, Extension::Pex])
+    };
+
+    // The extended handshake goes first so the peer can use our extensions right away
+    if handshake.supports_extensions() {
+        let payload = registry
+            .handshake(addr.ip(), metadata_size)
+            .to_bytes()
+            .map_err(|err| invalid_data(&err.to_string()))?;
+
+        Message::Extended {
+            id: extension::HANDSHAKE_ID,
+            payload,
+        }
+        .write(&mut writer)
+        .await?;
+    }
+
+    if bitfield.as_bytes().iter().any(|byte| *byte != 0) {
+        Message::Bitfield {
+            bitfield: bitfield.as_bytes().to_vec(),
+        }
+        .write(&mut writer)
+        .await?;
+    }
+
+    if interested {
+        Message::Interested.write(&mut writer).await?;
+    }
+
+    loop {
+        // Nothing left to exchange once both sides are seeders
+        if !interested && available.is_full() {
+            return Ok(());
+        }
+
+        let message = tokio::select! {
+            message = messages.recv() => match message {
+                Some(message) => message?,
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            },
+            have = haves.recv() => {
+                if let Ok(index) = have {
+                    Message::Have { index }.write(&mut writer).await?;
+                }
+
+                if interested && download.lock().await.pieces.is_complete() {
+                    interested = false;
+                    Message::NotInterested.write(&mut writer).await?;
+                }
+
+                continue;
+            }
+            _ = keep_alive.tick() => {
+                Message::KeepAlive.write(&mut writer).await?;
+                continue;
+            }
+            _ = pex_interval.tick() => {
+                if let Some(id) = registry.id(Extension::Pex) {
+                    let message = {
+                        let download = download.lock().await;
+                        pex.message(download.connected.iter().filter(|peer| **peer != addr))
+                    };
+
+                    if let Some(payload) = message {
+                        Message::Extended { id, payload }.write(&mut writer).await?;
+                    }
+                }
+
+                continue;
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        };
+
+        deadline = Instant::now() + RECEIVE_TIMEOUT;
+
+        match message {
+            Message::Choke => {
+                choked = true;
+
+                let mut download = download.lock().await;
+                for block in pending.drain(..) {
+                    download.pieces.release(block);
+                }
+            }
+            Message::Unchoke => choked = false,
+            Message::Interested => {
+                if choking {
+                    choking = false;
+                    Message::Unchoke.write(&mut writer).await?;
+                }
+            }
+            Message::NotInterested => {
+                if !choking {
+                    choking = true;
+                    Message::Choke.write(&mut writer).await?;
+                }
+            }
+            Message::Have { index } => available.set(index as usize),
+            Message::Bitfield { bitfield } => available = Bitfield::from_bytes(&bitfield, len),
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => {
+                if choking || length > MAX_REQUEST_LENGTH {
+                    continue;
+                }
+
+                let block = {
+                    let mut download = download.lock().await;
+
+                    if !download.pieces.has(index)
+                        || begin as u64 + length as u64 > download.pieces.piece_length(index) as u64
+                    {
+                        continue;
+                    }
+
+                    download.read_block(index, begin, length)?
+                };
+
+                Message::Piece {
+                    index,
+                    begin,
+                    block,
+                }
+                .write(&mut writer)
+                .await?;
+            }
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                pending.retain(|pending| pending.index != index || pending.begin != begin);
+
+                let completion = download.lock().await.pieces.receive(index, begin, &block);
+
+                match completion {
+                    Some(Completion::Verified(data)) => {
+                        match download::write_piece(download, index, data).await {
+                            Ok(()) => println!("{}: piece {} verified", addr, index),
+                            Err(err) => eprintln!("{}: piece {}: {}", addr, index, err),
+                        }
+                    }
+                    Some(Completion::Failed) => {
+                        eprintln!("{}: piece {} failed verification", addr, index);
+                    }
+                    None => {}
+                }
+            }
+            Message::Extended {
+                id: extension::HANDSHAKE_ID,
+                payload,
+            } => {
+                let handshake = extension::Handshake::from_bytes(&payload)
+                    .map_err(|err| invalid_data(&err.to_string()))?;
+
+                registry.negotiate(&handshake);
+
+                if handshake.reqq > 0 {
+                    pipeline_length = PIPELINE_LENGTH.min(handshake.reqq as usize);
+                }
+            }
+            Message::Extended { id, payload } => match registry.route(id) {
+                Some(Extension::Metadata) => {
+                    let reply = metadata::respond(&download.lock().await.metadata, &payload);
+
+                    if let (Some(payload), Some(id)) = (reply, registry.id(Extension::Metadata)) {
+                        Message::Extended { id, payload }.write(&mut writer).await?;
+                    }
+                }
+                // A bad message only costs us its peers, not the session
+                Some(Extension::Pex) => match pex.receive(&payload) {
+                    Ok(changes) => {
+                        let mut download = download.lock().await;
+                        download.add_peers(changes.added);
+                        download.drop_peers(&changes.dropped);
+                    }
+                    Err(err) => eprintln!("{}: ut_pex: {}", addr, err),
+                },
+                None => {}
+            },
+            Message::KeepAlive | Message::Cancel { .. } | Message::Unknown { .. } => {}
+        }
+
+        if choked || !interested {
+            continue;
+        }
+
+        let mut requests = Vec::new();
+
+        {
+            let mut download = download.lock().await;
+
+            while pending.len() < pipeline_length {
+                match download.pieces.pick(&available, pending) {
+                    Some(block) => {
+                        pending.push(block);
+                        requests.push(block);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        for block in requests {
+            Message::Request {
+                index: block.index,
+                begin: block.begin,
+                length: block.length,
+            }
+            .write(&mut writer)
+            .await?;
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Handshake, Message, run};
+    use crate::download::Download;
+    use crate::metainfo::{Info, sha1};
+    use crate::storage::Storage;
+    use crate::tracker::Peer;
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn test_handshake() {
+        let handshake = Handshake::new(&[1; 20], &[2; 20]);
+        let bytes = handshake.to_bytes();
+
+        assert_eq!(bytes.len(), 68);
+        assert_eq!(&bytes[..20], b"\x13BitTorrent protocol");
+        assert_eq!(&bytes[20..28], b"\0\0\0\0\0\x10\0\0");
+        assert_eq!(Handshake::from_bytes(&bytes).unwrap(), handshake);
+        assert!(handshake.supports_extensions());
+    }
+
+    #[test]
+    fn test_handshake_invalid_protocol() {
+        let mut bytes = Handshake::new(&[1; 20], &[2; 20]).to_bytes();
+        bytes[1] = b'b';
+
+        assert!(Handshake::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        assert_eq!(Message::KeepAlive.to_bytes(), b"\0\0\0\0");
+        assert_eq!(Message::from_bytes(b"").unwrap(), Message::KeepAlive);
+    }
+
+    #[test]
+    fn test_request() {
+        let message = Message::Request {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        };
+        let bytes = message.to_bytes();
+
+        assert_eq!(bytes, b"\0\0\0\x0d\x06\0\0\0\x01\0\0\x40\0\0\0\x40\0");
+        assert_eq!(Message::from_bytes(&bytes[4..]).unwrap(), message);
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        assert!(Message::from_bytes(b"\x04\0\0").is_err());
+        assert!(Message::from_bytes(b"\x00\0").is_err());
+        assert!(Message::from_bytes(b"\x14").is_err());
+    }
+
+    #[test]
+    fn test_unknown_id() {
+        let message = Message::Unknown {
+            id: 9,
+            payload: vec![0x1a, 0xe1],
+        };
+
+        assert_eq!(Message::from_bytes(b"\x09\x1a\xe1").unwrap(), message);
+        assert_eq!(message.to_bytes(), b"\0\0\0\x03\x09\x1a\xe1");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_message_keeps_session() {
+        let data = [7u8; 16];
+        let hashes = sha1(&data);
+        let info = Info::Single {
+            length: 16,
+            name: String::from("file"),
+            piece_length: 16,
+            pieces: &hashes,
+            private: None,
+            source: None,
+        };
+
+        let root = std::env::temp_dir().join(format!("shiina-peer-{}", std::process::id()));
+        let storage = Storage::new(&info, &root).unwrap();
+        let download = Download::new(Vec::new(), vec![1; 20], &info, storage);
+        let info_hash = download.info_hash.clone();
+        let download = Arc::new(Mutex::new(download));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = Peer {
+            addr: listener.local_addr().unwrap(),
+            peer_id: None,
+        };
+        let session = tokio::spawn(run(peer, download));
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        Handshake::read(&mut stream).await.unwrap();
+        Handshake::new(&info_hash, &[2; 20])
+            .write(&mut stream)
+            .await
+            .unwrap();
+
+        // PORT, then a message the session has to answer
+        stream.write_all(b"\0\0\0\x03\x09\x1a\xe1").await.unwrap();
+        Message::Interested.write(&mut stream).await.unwrap();
+
+        let unchoked = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if Message::read(&mut stream).await.unwrap() == Message::Unchoke {
+                    break;
+                }
+            }
+        });
+        assert!(unchoked.await.is_ok());
+        assert!(!session.is_finished());
+
+        session.abort();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let messages = [
+            Message::KeepAlive,
+            Message::Choke,
+            Message::Unchoke,
+            Message::Interested,
+            Message::NotInterested,
+            Message::Have { index: 7 },
+            Message::Bitfield {
+                bitfield: vec![0b1010_0000],
+            },
+            Message::Piece {
+                index: 2,
+                begin: 0,
+                block: vec![1, 2, 3],
+            },
+            Message::Cancel {
+                index: 2,
+                begin: 0,
+                length: 3,
+            },
+            Message::Extended {
+                id: 0,
+                payload: b"de".to_vec(),
+            },
+        ];
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        for message in &messages {
+            message.write(&mut client).await.unwrap();
+        }
+
+        for message in &messages {
+            assert_eq!(&Message::read(&mut server).await.unwrap(), message);
+        }
+    }
+}
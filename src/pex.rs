@@ -0,0 +1,198 @@
+use crate::bencode;
+use crate::tracker::{COMPACT_V4_LENGTH, COMPACT_V6_LENGTH, Peer, compact};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const INTERVAL: Duration = Duration::from_secs(60);
+const MAX_PEERS: usize = 50;
+// Peers should not send more than one message a minute, some slack is left for
+// timers that are not exactly aligned
+const MIN_RECEIVE_INTERVAL: Duration = Duration::from_secs(45);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PexMessage {
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    added: Vec<u8>,
+    #[serde(
+        default,
+        rename = "added.f",
+        skip_serializing_if = "Vec::is_empty",
+        with = "serde_bytes"
+    )]
+    added_f: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    added6: Vec<u8>,
+    #[serde(
+        default,
+        rename = "added6.f",
+        skip_serializing_if = "Vec::is_empty",
+        with = "serde_bytes"
+    )]
+    added6_f: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    dropped: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_bytes")]
+    dropped6: Vec<u8>,
+}
+
+// Peers a neighbour connected to and disconnected from since its last message
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub added: Vec<Peer>,
+    pub dropped: Vec<SocketAddr>,
+}
+
+// Peer exchange (BEP 11) with a single peer. Every message only contains the
+// changes since the previous one.
+#[derive(Debug, Default)]
+pub struct Pex {
+    last_received: Option<Instant>,
+    sent: HashSet<SocketAddr>,
+}
+
+impl Pex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Builds the next message from the peers we are connected to, or None if
+    // nothing changed since the last one
+    pub fn message<'a, I>(&mut self, connected: I) -> Option<Vec<u8>>
+    where
+        I: IntoIterator<Item = &'a SocketAddr>,
+    {
+        let connected: HashSet<SocketAddr> = connected.into_iter().copied().collect();
+        let mut message = PexMessage::default();
+
+        let added: Vec<SocketAddr> = connected
+            .difference(&self.sent)
+            .take(MAX_PEERS)
+            .copied()
+            .collect();
+        let dropped: Vec<SocketAddr> = self
+            .sent
+            .difference(&connected)
+            .take(MAX_PEERS)
+            .copied()
+            .collect();
+
+        if added.is_empty() && dropped.is_empty() {
+            return None;
+        }
+
+        for addr in &added {
+            let (peers, flags) = match addr {
+                SocketAddr::V4(_) => (&mut message.added, &mut message.added_f),
+                SocketAddr::V6(_) => (&mut message.added6, &mut message.added6_f),
+            };
+            peers.extend_from_slice(&compact(addr));
+            flags.push(0);
+            self.sent.insert(*addr);
+        }
+
+        for addr in &dropped {
+            match addr {
+                SocketAddr::V4(_) => message.dropped.extend_from_slice(&compact(addr)),
+                SocketAddr::V6(_) => message.dropped6.extend_from_slice(&compact(addr)),
+            }
+            self.sent.remove(addr);
+        }
+
+        bencode::to_bytes(&message).ok()
+    }
+
+    // Returns the peers added and dropped by a message, at most MAX_PEERS of each.
+    // Messages arriving too soon after the previous one are ignored.
+    pub fn receive(&mut self, payload: &[u8]) -> io::Result<Changes> {
+        let message = bencode::from_bytes::<PexMessage>(payload)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+        if self
+            .last_received
+            .is_some_and(|last| last.elapsed() < MIN_RECEIVE_INTERVAL)
+        {
+            return Ok(Changes::default());
+        }
+
+        self.last_received = Some(Instant::now());
+
+        Ok(Changes {
+            added: message
+                .added
+                .chunks_exact(COMPACT_V4_LENGTH)
+                .chain(message.added6.chunks_exact(COMPACT_V6_LENGTH))
+                .filter_map(Peer::from_compact)
+                .take(MAX_PEERS)
+                .collect(),
+            dropped: message
+                .dropped
+                .chunks_exact(COMPACT_V4_LENGTH)
+                .chain(message.dropped6.chunks_exact(COMPACT_V6_LENGTH))
+                .filter_map(Peer::from_compact)
+                .map(|peer| peer.addr)
+                .take(MAX_PEERS)
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_PEERS, Pex};
+    use std::net::SocketAddr;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_message() {
+        let mut pex = Pex::new();
+        let a = addr("10.0.0.1:6881");
+        let b = addr("[::1]:80");
+
+        assert_eq!(
+            pex.message(&[a, b]).unwrap(),
+            b"d5:added6:\x0a\0\0\x01\x1a\xe17:added.f1:\x006:added618:\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\x508:added6.f1:\x00e"
+        );
+        assert_eq!(pex.message(&[a, b]), None);
+        assert_eq!(
+            pex.message(&[b]).unwrap(),
+            b"d7:dropped6:\x0a\0\0\x01\x1a\xe1e"
+        );
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut pex = Pex::new();
+        let payload = b"d5:added12:\x7f\0\0\x01\x1a\xe1\x0a\0\0\x02\0\x507:added.f2:\0\x007:dropped6:\x0a\0\0\x03\0\x50e";
+
+        let changes = pex.receive(payload).unwrap();
+
+        assert_eq!(changes.added.len(), 2);
+        assert_eq!(changes.added[0].addr, addr("127.0.0.1:6881"));
+        assert_eq!(changes.added[1].addr, addr("10.0.0.2:80"));
+        assert_eq!(changes.dropped, [addr("10.0.0.3:80")]);
+
+        // A second message within a minute is ignored
+        let changes = pex.receive(payload).unwrap();
+        assert!(changes.added.is_empty() && changes.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_receive_cap() {
+        let mut pex = Pex::new();
+        let added: Vec<u8> = (0..MAX_PEERS as u8 * 2)
+            .flat_map(|n| [10, 0, 0, n, 0, 80])
+            .collect();
+        let mut payload = format!("d5:added{}:", added.len()).into_bytes();
+        payload.extend_from_slice(&added);
+        payload.push(b'e');
+
+        assert_eq!(pex.receive(&payload).unwrap().added.len(), MAX_PEERS);
+    }
+}
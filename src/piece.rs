@@ -0,0 +1,450 @@
+use crate::metainfo::{Info, sha1};
+use std::fmt;
+
+pub const BLOCK_SIZE: u32 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    pub begin: u32,
+    pub index: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bitfield {
+    pub fn new(len: usize) -> Self {
+        Self {
+            bytes: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Self {
+        let mut bitfield = Self::new(len);
+        let n = bitfield.bytes.len().min(bytes.len());
+        bitfield.bytes[..n].copy_from_slice(&bytes[..n]);
+
+        // Spare bits at the end must be ignored
+        if !len.is_multiple_of(8)
+            && let Some(last) = bitfield.bytes.last_mut()
+        {
+            *last &= 0xff << (8 - len % 8);
+        }
+
+        bitfield
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn has(&self, index: usize) -> bool {
+        index < self.len && self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        (0..self.len).all(|index| self.has(index))
+    }
+
+    pub fn set(&mut self, index: usize) {
+        if index < self.len {
+            self.bytes[index / 8] |= 0x80 >> (index % 8);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Completion {
+    Failed,
+    Verified(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockState {
+    Missing,
+    Received,
+    Requested,
+}
+
+struct Piece {
+    blocks: Vec<BlockState>,
+    data: Vec<u8>,
+    hash: Vec<u8>,
+    length: u32,
+    verified: bool,
+}
+
+impl Piece {
+    fn new(hash: &[u8], length: u32) -> Self {
+        Self {
+            blocks: vec![BlockState::Missing; length.div_ceil(BLOCK_SIZE) as usize],
+            data: Vec::new(),
+            hash: hash.to_vec(),
+            length,
+            verified: false,
+        }
+    }
+
+    fn block_length(&self, block: usize) -> u32 {
+        BLOCK_SIZE.min(self.length - block as u32 * BLOCK_SIZE)
+    }
+
+    fn is_started(&self) -> bool {
+        self.blocks
+            .iter()
+            .any(|state| *state != BlockState::Missing)
+    }
+
+    fn reset(&mut self) {
+        self.blocks.fill(BlockState::Missing);
+        self.data = Vec::new();
+    }
+}
+
+pub struct PieceManager {
+    pieces: Vec<Piece>,
+}
+
+impl fmt::Debug for PieceManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PieceManager")
+            .field("pieces", &self.pieces.len())
+            .field(
+                "verified",
+                &self.pieces.iter().filter(|piece| piece.verified).count(),
+            )
+            .finish()
+    }
+}
+
+impl PieceManager {
+    pub fn new(info: &Info) -> Self {
+        let pieces = (0..info.piece_count())
+            .map(|index| Piece::new(info.piece_hash(index), info.piece_size(index) as u32))
+            .collect();
+
+        Self { pieces }
+    }
+
+    pub fn bitfield(&self) -> Bitfield {
+        let mut bitfield = Bitfield::new(self.pieces.len());
+        for (index, piece) in self.pieces.iter().enumerate() {
+            if piece.verified {
+                bitfield.set(index);
+            }
+        }
+        bitfield
+    }
+
+    // Marks a piece as verified if `hash` is the hash of its data, e.g. when resuming
+    // from disk
+    pub fn check(&mut self, index: u32, hash: &[u8]) -> bool {
+        let piece = &mut self.pieces[index as usize];
+        piece.verified = hash == piece.hash;
+        piece.verified
+    }
+
+    pub fn has(&self, index: u32) -> bool {
+        self.pieces
+            .get(index as usize)
+            .is_some_and(|piece| piece.verified)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pieces.iter().all(|piece| piece.verified)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    // Picks the next block to request from a peer that has the pieces in `available`.
+    // Pieces that are already in progress are finished first. Once every block has
+    // been requested, blocks not in `pending` are handed out again (endgame mode).
+    pub fn pick(&mut self, available: &Bitfield, pending: &[Block]) -> Option<Block> {
+        let candidates = || {
+            self.pieces
+                .iter()
+                .enumerate()
+                .filter(|(index, piece)| !piece.verified && available.has(*index))
+        };
+
+        let found = candidates()
+            .filter(|(_, piece)| piece.is_started())
+            .chain(candidates().filter(|(_, piece)| !piece.is_started()))
+            .find_map(|(index, piece)| {
+                piece
+                    .blocks
+                    .iter()
+                    .position(|state| *state == BlockState::Missing)
+                    .map(|block| (index, block))
+            })
+            .or_else(|| {
+                candidates().find_map(|(index, piece)| {
+                    (0..piece.blocks.len())
+                        .find(|block| {
+                            piece.blocks[*block] == BlockState::Requested
+                                && !pending.iter().any(|pending| {
+                                    pending.index as usize == index
+                                        && pending.begin == *block as u32 * BLOCK_SIZE
+                                })
+                        })
+                        .map(|block| (index, block))
+                })
+            });
+
+        let (index, block) = found?;
+        let piece = &mut self.pieces[index];
+        piece.blocks[block] = BlockState::Requested;
+
+        Some(Block {
+            begin: block as u32 * BLOCK_SIZE,
+            index: index as u32,
+            length: piece.block_length(block),
+        })
+    }
+
+    pub fn piece_length(&self, index: u32) -> u32 {
+        self.pieces[index as usize].length
+    }
+
+    pub fn receive(&mut self, index: u32, begin: u32, data: &[u8]) -> Option<Completion> {
+        let piece = self.pieces.get_mut(index as usize)?;
+        let block = (begin / BLOCK_SIZE) as usize;
+
+        if piece.verified
+            || !begin.is_multiple_of(BLOCK_SIZE)
+            || block >= piece.blocks.len()
+            || data.len() as u32 != piece.block_length(block)
+            || piece.blocks[block] == BlockState::Received
+        {
+            return None;
+        }
+
+        if piece.data.is_empty() {
+            piece.data = vec![0; piece.length as usize];
+        }
+
+        piece.data[begin as usize..begin as usize + data.len()].copy_from_slice(data);
+        piece.blocks[block] = BlockState::Received;
+
+        if piece
+            .blocks
+            .iter()
+            .any(|state| *state != BlockState::Received)
+        {
+            return None;
+        }
+
+        let data = std::mem::take(&mut piece.data);
+
+        // Only counted as verified once the data is written, see verify
+        if sha1(&data) == piece.hash {
+            Some(Completion::Verified(data))
+        } else {
+            piece.reset();
+            Some(Completion::Failed)
+        }
+    }
+
+    pub fn verify(&mut self, index: u32) {
+        if let Some(piece) = self.pieces.get_mut(index as usize) {
+            piece.verified = true;
+        }
+    }
+
+    // Fetches a received piece again, e.g. when it could not be written
+    pub fn reset(&mut self, index: u32) {
+        if let Some(piece) = self.pieces.get_mut(index as usize) {
+            piece.reset();
+        }
+    }
+
+    // Returns a block that will not arrive (choke, disconnect) to the pool
+    pub fn release(&mut self, block: Block) {
+        if let Some(piece) = self.pieces.get_mut(block.index as usize) {
+            let block = (block.begin / BLOCK_SIZE) as usize;
+
+            if piece.blocks.get(block) == Some(&BlockState::Requested) {
+                piece.blocks[block] = BlockState::Missing;
+            }
+        }
+    }
+
+    pub fn verified_bytes(&self) -> i64 {
+        self.pieces
+            .iter()
+            .filter(|piece| piece.verified)
+            .map(|piece| piece.length as i64)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BLOCK_SIZE, Bitfield, Block, Completion, PieceManager};
+    use crate::metainfo::{Info, sha1};
+
+    fn hashes(pieces: &[&[u8]]) -> Vec<u8> {
+        pieces.iter().flat_map(|piece| sha1(piece)).collect()
+    }
+
+    fn info(pieces: &[u8], piece_length: i64, length: i64) -> Info<'_> {
+        Info::Single {
+            length,
+            name: String::from("file"),
+            piece_length,
+            pieces,
+            private: None,
+            source: None,
+        }
+    }
+
+    fn full(len: usize) -> Bitfield {
+        Bitfield::from_bytes(&vec![0xff; len.div_ceil(8)], len)
+    }
+
+    #[test]
+    fn test_bitfield_spare_bits() {
+        let bitfield = Bitfield::from_bytes(&[0xff, 0xff], 10);
+
+        assert!(bitfield.has(9));
+        assert!(!bitfield.has(10));
+        assert_eq!(bitfield.as_bytes(), &[0xff, 0xc0]);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let data = vec![7u8; BLOCK_SIZE as usize * 2 + 100];
+        let hashes = hashes(&[&data]);
+        let mut manager = PieceManager::new(&info(&hashes, data.len() as i64, data.len() as i64));
+        let available = full(1);
+
+        let blocks: Vec<Block> = std::iter::from_fn(|| manager.pick(&available, &[]))
+            .take(3)
+            .collect();
+
+        assert_eq!(blocks[0].length, BLOCK_SIZE);
+        assert_eq!(blocks[2].begin, BLOCK_SIZE * 2);
+        assert_eq!(blocks[2].length, 100);
+
+        for block in &blocks[..2] {
+            let begin = block.begin as usize;
+            let data = &data[begin..begin + block.length as usize];
+            assert!(manager.receive(block.index, block.begin, data).is_none());
+        }
+
+        assert!(matches!(
+            manager.receive(0, BLOCK_SIZE * 2, &data[..100]),
+            Some(Completion::Verified(piece)) if piece == data
+        ));
+        assert!(!manager.is_complete());
+        assert_eq!(manager.pick(&available, &[]), None);
+
+        manager.verify(0);
+        assert!(manager.is_complete());
+        assert_eq!(manager.verified_bytes(), data.len() as i64);
+    }
+
+    #[test]
+    fn test_last_piece() {
+        let hashes = hashes(&[&[1; 8], &[2; 8], &[3; 3]]);
+        let mut manager = PieceManager::new(&info(&hashes, 8, 19));
+        let mut available = Bitfield::new(3);
+        available.set(2);
+
+        assert_eq!(manager.len(), 3);
+        assert_eq!(
+            manager.pick(&available, &[]),
+            Some(Block {
+                begin: 0,
+                index: 2,
+                length: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let hashes = hashes(&[b"abc", b"def"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 6));
+
+        assert!(manager.check(1, &sha1(b"def")));
+        assert!(!manager.check(0, &sha1(b"abd")));
+        assert!(manager.has(1));
+        assert!(!manager.has(0));
+        assert_eq!(manager.bitfield().as_bytes(), &[0b0100_0000]);
+        assert_eq!(manager.verified_bytes(), 3);
+    }
+
+    #[test]
+    fn test_failed_piece() {
+        let hashes = hashes(&[b"abc"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 3));
+        let available = full(1);
+
+        let block = manager.pick(&available, &[]).unwrap();
+
+        assert!(matches!(
+            manager.receive(block.index, block.begin, b"abd"),
+            Some(Completion::Failed)
+        ));
+        assert_eq!(manager.verified_bytes(), 0);
+        assert_eq!(manager.pick(&available, &[]), Some(block));
+    }
+
+    #[test]
+    fn test_reset() {
+        let hashes = hashes(&[b"abc"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 3));
+        let available = full(1);
+
+        let block = manager.pick(&available, &[]).unwrap();
+        assert!(matches!(
+            manager.receive(block.index, block.begin, b"abc"),
+            Some(Completion::Verified(_))
+        ));
+
+        manager.reset(0);
+
+        assert!(!manager.has(0));
+        assert_eq!(manager.pick(&available, &[]), Some(block));
+    }
+
+    #[test]
+    fn test_endgame() {
+        let hashes = hashes(&[b"abc"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 3));
+        let available = full(1);
+
+        let block = manager.pick(&available, &[]).unwrap();
+
+        assert_eq!(manager.pick(&available, &[block]), None);
+        assert_eq!(manager.pick(&available, &[]), Some(block));
+    }
+
+    #[test]
+    fn test_release() {
+        let hashes = hashes(&[b"abc"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 3));
+        let available = full(1);
+
+        let block = manager.pick(&available, &[]).unwrap();
+        manager.release(block);
+
+        assert_eq!(manager.pick(&available, &[block]), Some(block));
+    }
+
+    #[test]
+    fn test_unavailable() {
+        let hashes = hashes(&[b"abc"]);
+        let mut manager = PieceManager::new(&info(&hashes, 3, 3));
+
+        assert_eq!(manager.pick(&Bitfield::new(1), &[]), None);
+    }
+}
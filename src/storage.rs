@@ -0,0 +1,226 @@
+use crate::metainfo::Info;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug)]
+struct Entry {
+    length: i64,
+    offset: i64,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Storage {
+    files: Vec<Entry>,
+}
+
+impl Storage {
+    pub fn new(info: &Info, root: &Path) -> io::Result<Self> {
+        let base = root.join(component(info.name())?);
+        let mut files = Vec::new();
+
+        for file in info.files() {
+            let mut path = base.clone();
+
+            if let Info::Multi { .. } = info {
+                if file.path.is_empty() {
+                    return Err(invalid_path(info.name()));
+                }
+
+                for part in file.path {
+                    path.push(component(part)?);
+                }
+            }
+
+            files.push(Entry {
+                length: file.length,
+                offset: file.offset,
+                path,
+            });
+        }
+
+        for file in &files {
+            if let Some(parent) = file.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&file.path)?;
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn read(&self, offset: i64, length: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; length];
+
+        for (file, position, range) in self.segments(offset, length)? {
+            let mut handle = fs::File::open(&file.path)?;
+            handle.seek(SeekFrom::Start(position))?;
+            handle.read_exact(&mut data[range])?;
+        }
+
+        Ok(data)
+    }
+
+    // Maps the torrent byte range [offset, offset + length) onto the files it spans,
+    // returning each file together with the position in that file and the matching
+    // range in the caller's buffer
+    fn segments(&self, offset: i64, length: usize) -> io::Result<Vec<(&Entry, u64, Range<usize>)>> {
+        let end = offset + length as i64;
+
+        if offset < 0
+            || end
+                > self
+                    .files
+                    .last()
+                    .map_or(0, |file| file.offset + file.length)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Range out of bounds",
+            ));
+        }
+
+        Ok(self
+            .files
+            .iter()
+            .filter(|file| {
+                file.length > 0 && file.offset < end && offset < file.offset + file.length
+            })
+            .map(|file| {
+                let start = offset.max(file.offset);
+                let stop = end.min(file.offset + file.length);
+                let range = (start - offset) as usize..(stop - offset) as usize;
+                (file, (start - file.offset) as u64, range)
+            })
+            .collect())
+    }
+
+    pub fn write(&self, offset: i64, data: &[u8]) -> io::Result<()> {
+        for (file, position, range) in self.segments(offset, data.len())? {
+            let mut handle = OpenOptions::new().write(true).open(&file.path)?;
+            handle.seek(SeekFrom::Start(position))?;
+            handle.write_all(&data[range])?;
+        }
+
+        Ok(())
+    }
+}
+
+// Only allow plain file names so a malicious torrent cannot escape the download directory
+fn component(name: &str) -> io::Result<&str> {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(part)), None) if part == name => Ok(name),
+        _ => Err(invalid_path(name)),
+    }
+}
+
+fn invalid_path(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid path component: {:?}", name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Storage, component};
+    use crate::metainfo::{File, Info};
+    use std::path::PathBuf;
+
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("shiina-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    fn multi(files: &[(&[&str], i64)]) -> Info<'static> {
+        Info::Multi {
+            files: files
+                .iter()
+                .map(|(path, length)| File {
+                    length: *length,
+                    path: path.iter().map(|part| part.to_string()).collect(),
+                })
+                .collect(),
+            name: String::from("torrent"),
+            piece_length: 4,
+            pieces: &[],
+            private: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_component() {
+        assert!(component("file").is_ok());
+        assert!(component("..").is_err());
+        assert!(component(".").is_err());
+        assert!(component("").is_err());
+        assert!(component("/etc").is_err());
+        assert!(component("a/b").is_err());
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        let root = root("invalid-path");
+        let info = multi(&[(&["..", "escape"], 1)]);
+
+        assert!(Storage::new(&info, &root).is_err());
+    }
+
+    #[test]
+    fn test_single() {
+        let root = root("single");
+        let info = Info::Single {
+            length: 6,
+            name: String::from("file"),
+            piece_length: 4,
+            pieces: &[],
+            private: None,
+            source: None,
+        };
+        let storage = Storage::new(&info, &root).unwrap();
+
+        storage.write(4, b"ef").unwrap();
+        storage.write(0, b"abcd").unwrap();
+
+        assert_eq!(std::fs::read(root.join("file")).unwrap(), b"abcdef");
+        assert_eq!(storage.read(2, 4).unwrap(), b"cdef");
+        assert!(storage.read(4, 4).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_multi() {
+        let root = root("multi");
+        let info = multi(&[
+            (&["a"], 3),
+            (&["empty"], 0),
+            (&["dir", "b"], 2),
+            (&["c"], 3),
+        ]);
+        let storage = Storage::new(&info, &root).unwrap();
+
+        storage.write(0, b"abcd").unwrap();
+        storage.write(4, b"efgh").unwrap();
+
+        let base = root.join("torrent");
+        assert_eq!(std::fs::read(base.join("a")).unwrap(), b"abc");
+        assert_eq!(std::fs::read(base.join("empty")).unwrap(), b"");
+        assert_eq!(std::fs::read(base.join("dir").join("b")).unwrap(), b"de");
+        assert_eq!(std::fs::read(base.join("c")).unwrap(), b"fgh");
+        assert_eq!(storage.read(2, 4).unwrap(), b"cdef");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt::{self, Display};
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Failure(String),
+    Status(u16),
+    Timeout,
+    Malformed(String),
+    InvalidUrl(String),
+    Http(reqwest::Error),
+    Io(io::Error),
+    NoTrackers,
+    // The error of every tracker tried, after its URL
+    Failed(Vec<(String, Error)>),
+}
+
+impl From<crate::bencode::Error> for Error {
+    fn from(err: crate::bencode::Error) -> Self {
+        Error::Malformed(err.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Http(err)
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Failure(reason) => write!(f, "Tracker failure: {}", reason),
+            Error::Status(status) => write!(f, "Unexpected HTTP status {}", status),
+            Error::Timeout => f.write_str("Tracker did not respond"),
+            Error::Malformed(msg) => write!(f, "Malformed tracker response: {}", msg),
+            Error::InvalidUrl(msg) => write!(f, "Invalid tracker URL: {}", msg),
+            Error::Http(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::NoTrackers => f.write_str("No trackers"),
+            Error::Failed(errors) => {
+                for (index, (announce, err)) in errors.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str("\n")?;
+                    }
+                    write!(f, "{}: {}", announce, err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
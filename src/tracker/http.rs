@@ -0,0 +1,211 @@
+use crate::tracker::{Announce, Error, Event, Response, Scrape};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct Failure<'a> {
+    #[serde(rename = "failure reason")]
+    failure_reason: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScrapeResponse {
+    #[serde(default)]
+    files: BTreeMap<ByteBuf, Scrape>,
+}
+
+pub fn client() -> Client {
+    Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
+
+pub async fn announce(
+    client: &Client,
+    announce_url: &str,
+    announce: &Announce<'_>,
+) -> Result<Response, Error> {
+    let mut params = Vec::from([
+        ("compact", String::from("1")),
+        ("downloaded", announce.downloaded.to_string()),
+        ("left", announce.left.to_string()),
+        ("port", announce.port.to_string()),
+        ("uploaded", announce.uploaded.to_string()),
+    ]);
+
+    if announce.event != Event::Regular {
+        params.push(("event", announce.event.as_str().to_string()));
+    }
+
+    if let Some(ip) = crate::IP {
+        params.push(("ip", ip));
+    }
+
+    let url = Url::parse_with_params(announce_url, params)
+        .map_err(|err| Error::InvalidUrl(err.to_string()))?;
+
+    // Add these params separatly to avoid default URL encoding
+    let mut url = format!(
+        "{}&info_hash={}&peer_id={}",
+        url,
+        url_encode(announce.info_hash),
+        url_encode(announce.peer_id),
+    );
+
+    if let Some(tracker_id) = announce.tracker_id {
+        url.push_str(&format!("&trackerid={}", url_encode(tracker_id)));
+    }
+
+    let response = client.get(url).send().await?;
+    let status = response.status().as_u16();
+    let body = response.bytes().await?;
+
+    parse_response(status, &body)
+}
+
+pub async fn scrape(
+    client: &Client,
+    scrape_url: &str,
+    info_hashes: &[&[u8]],
+) -> Result<BTreeMap<Vec<u8>, Scrape>, Error> {
+    let mut url = Url::parse(scrape_url)
+        .map_err(|err| Error::InvalidUrl(err.to_string()))?
+        .to_string();
+
+    for (index, info_hash) in info_hashes.iter().enumerate() {
+        let separator = if index == 0 && !url.contains('?') {
+            '?'
+        } else {
+            '&'
+        };
+        url.push_str(&format!("{}info_hash={}", separator, url_encode(info_hash)));
+    }
+
+    let response = client.get(url).send().await?;
+    let status = response.status().as_u16();
+    let body = response.bytes().await?;
+
+    let response = parse_response::<ScrapeResponse>(status, &body)?;
+
+    Ok(response
+        .files
+        .into_iter()
+        .map(|(info_hash, scrape)| (info_hash.into_vec(), scrape))
+        .collect())
+}
+
+// Derives the scrape URL by replacing `announce` at the start of the last path
+// segment with `scrape`. Trackers whose URL does not follow this convention do
+// not support scraping.
+pub fn scrape_url(announce_url: &str) -> Option<String> {
+    let (path, query) = match announce_url.find('?') {
+        Some(index) => announce_url.split_at(index),
+        None => (announce_url, ""),
+    };
+    let slash = path.rfind('/')?;
+    let rest = path[slash + 1..].strip_prefix("announce")?;
+
+    Some(format!("{}scrape{}{}", &path[..=slash], rest, query))
+}
+
+// Some trackers send `failure reason` with an error status, so the body is
+// looked at before the status
+fn parse_response<'a, T>(status: u16, body: &'a [u8]) -> Result<T, Error>
+where
+    T: Deserialize<'a>,
+{
+    if let Ok(failure) = crate::bencode::from_bytes::<Failure>(body) {
+        return Err(Error::Failure(failure.failure_reason.to_owned()));
+    }
+
+    if !(200..300).contains(&status) {
+        return Err(Error::Status(status));
+    }
+
+    Ok(crate::bencode::from_bytes(body)?)
+}
+
+fn url_encode(bytes: &[u8]) -> String {
+    let mut res = String::new();
+    bytes
+        .iter()
+        .for_each(|byte| res.push_str(&format!("%{:02x}", byte)));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ScrapeResponse, parse_response, scrape_url};
+    use crate::tracker::{Error, Response};
+
+    #[test]
+    fn test_failure_reason() {
+        let body = b"d14:failure reason22:torrent not registerede";
+
+        assert!(matches!(
+            parse_response::<Response>(200, body),
+            Err(Error::Failure(reason)) if reason == "torrent not registered"
+        ));
+        assert!(matches!(
+            parse_response::<Response>(400, body),
+            Err(Error::Failure(_))
+        ));
+    }
+
+    #[test]
+    fn test_status() {
+        assert!(matches!(
+            parse_response::<Response>(404, b"<html></html>"),
+            Err(Error::Status(404))
+        ));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(matches!(
+            parse_response::<Response>(200, b"d8:intervali1800e"),
+            Err(Error::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_warning_and_tracker_id() {
+        let body = b"d8:intervali1800e5:peers0:10:tracker id3:abc15:warning message4:slowe";
+        let response = parse_response::<Response>(200, body).unwrap();
+
+        assert_eq!(response.tracker_id, b"abc");
+        assert_eq!(response.warning_message, "slow");
+    }
+
+    #[test]
+    fn test_scrape_url() {
+        assert_eq!(
+            scrape_url("http://example.com/announce").as_deref(),
+            Some("http://example.com/scrape")
+        );
+        assert_eq!(
+            scrape_url("http://example.com/x/announce.php?key=1").as_deref(),
+            Some("http://example.com/x/scrape.php?key=1")
+        );
+        assert_eq!(scrape_url("http://example.com/a"), None);
+        assert_eq!(scrape_url("http://example.com/announce/x"), None);
+    }
+
+    #[test]
+    fn test_scrape_response() {
+        let body =
+            b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei5e10:downloadedi50e10:incompletei10eeee";
+        let response = parse_response::<ScrapeResponse>(200, body).unwrap();
+        let scrape = &response.files[serde_bytes::Bytes::new(b"aaaaaaaaaaaaaaaaaaaa")];
+
+        assert_eq!(scrape.complete, 5);
+        assert_eq!(scrape.downloaded, 50);
+        assert_eq!(scrape.incomplete, 10);
+    }
+}
@@ -0,0 +1,808 @@
+mod error;
+mod http;
+mod udp;
+
+pub use error::Error;
+
+use crate::download::Download;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::time::Instant;
+
+pub const COMPACT_V4_LENGTH: usize = 6;
+pub const COMPACT_V6_LENGTH: usize = 18;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1800);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const STOPPED_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a tracker gets before the next one is tried, enough for the first two
+// attempts of a UDP tracker
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(60);
+const UNKNOWN_LEFT: i64 = 1 << 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub peer_id: Option<Vec<u8>>,
+}
+
+impl Peer {
+    // Parses the compact form: 4 (IPv4) or 16 (IPv6) address bytes followed by a
+    // 2-byte port, all in network byte order
+    pub fn from_compact(bytes: &[u8]) -> Option<Self> {
+        let (ip, port) = match bytes.len() {
+            COMPACT_V4_LENGTH => {
+                let octets: [u8; 4] = bytes[..4].try_into().unwrap();
+                (IpAddr::from(Ipv4Addr::from(octets)), &bytes[4..])
+            }
+            COMPACT_V6_LENGTH => {
+                let octets: [u8; 16] = bytes[..16].try_into().unwrap();
+                (IpAddr::from(Ipv6Addr::from(octets)), &bytes[16..])
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            addr: SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])),
+            peer_id: None,
+        })
+    }
+}
+
+// Inverse of Peer::from_compact
+pub fn compact(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+    bytes
+}
+
+#[derive(Debug, Deserialize)]
+struct DictionaryPeer {
+    ip: String,
+    #[serde(default, rename = "peer id", with = "serde_bytes")]
+    peer_id: Vec<u8>,
+    port: u16,
+}
+
+impl DictionaryPeer {
+    fn to_peer(&self, ip: IpAddr) -> Peer {
+        Peer {
+            addr: SocketAddr::new(ip, self.port),
+            peer_id: (!self.peer_id.is_empty()).then(|| self.peer_id.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Peers {
+    addrs: Vec<Peer>,
+    // Dictionary peers given by host name, resolved once the reply is in
+    hosts: Vec<DictionaryPeer>,
+}
+
+// Trackers send `peers` either as a list of dictionaries or, when `compact=1` is
+// honoured (BEP 23), as a string of concatenated compact peers
+struct PeersVisitor {
+    size: usize,
+}
+
+impl<'de> Visitor<'de> for PeersVisitor {
+    type Value = Peers;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a compact peer string or a list of peer dictionaries")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        if !v.len().is_multiple_of(self.size) {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+
+        Ok(Peers {
+            addrs: v
+                .chunks_exact(self.size)
+                .filter_map(Peer::from_compact)
+                .collect(),
+            hosts: Vec::new(),
+        })
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut peers = Peers::default();
+
+        while let Some(peer) = seq.next_element::<DictionaryPeer>()? {
+            match peer.ip.parse::<IpAddr>() {
+                Ok(ip) => peers.addrs.push(peer.to_peer(ip)),
+                Err(_) => peers.hosts.push(peer),
+            }
+        }
+
+        Ok(peers)
+    }
+}
+
+fn deserialize_peers<'de, D>(deserializer: D) -> Result<Peers, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(PeersVisitor {
+        size: COMPACT_V4_LENGTH,
+    })
+}
+
+fn deserialize_peers6<'de, D>(deserializer: D) -> Result<Vec<Peer>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Only compact peers are defined for peers6 (BEP 7)
+    let peers = deserializer.deserialize_any(PeersVisitor {
+        size: COMPACT_V6_LENGTH,
+    })?;
+    Ok(peers.addrs)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Response {
+    #[serde(default)]
+    interval: i64,
+    #[serde(default, rename = "min interval")]
+    min_interval: i64,
+    #[serde(default, deserialize_with = "deserialize_peers")]
+    peers: Peers,
+    #[serde(default, deserialize_with = "deserialize_peers6")]
+    peers6: Vec<Peer>,
+    #[serde(default, rename = "tracker id", with = "serde_bytes")]
+    tracker_id: Vec<u8>,
+    #[serde(default, rename = "warning message")]
+    warning_message: String,
+}
+
+// What the first tracker that responded to an announce gave us
+#[derive(Debug)]
+pub struct Announcement {
+    pub interval: Duration,
+    pub peers: Vec<Peer>,
+    pub tracker: String,
+    // The announce still succeeded, the tracker only wants it shown to the user
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Scrape {
+    #[serde(default)]
+    pub complete: i64,
+    #[serde(default)]
+    pub downloaded: i64,
+    #[serde(default)]
+    pub incomplete: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    Completed,
+    Regular,
+    Started,
+    Stopped,
+}
+
+impl Event {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Event::Completed => "completed",
+            Event::Regular => "",
+            Event::Started => "started",
+            Event::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Announce<'a> {
+    downloaded: i64,
+    event: Event,
+    info_hash: &'a [u8],
+    left: i64,
+    peer_id: &'a [u8],
+    port: u16,
+    tracker_id: Option<&'a [u8]>,
+    uploaded: i64,
+}
+
+enum Protocol {
+    Http(reqwest::Client),
+    Udp(udp::Client),
+}
+
+pub struct Tracker {
+    announce: String,
+    interval: i64,
+    min_interval: i64,
+    protocol: Protocol,
+    tracker_id: Option<Vec<u8>>,
+}
+
+impl Tracker {
+    pub fn new(announce: String) -> Self {
+        let protocol = if announce.starts_with("udp://") {
+            Protocol::Udp(udp::Client::new(&announce))
+        } else {
+            Protocol::Http(http::client())
+        };
+
+        Self {
+            announce,
+            interval: 0,
+            min_interval: 0,
+            protocol,
+            tracker_id: None,
+        }
+    }
+
+    // Time until the next regular announce, never shorter than `min interval`
+    fn interval(&self) -> Duration {
+        match self.interval.max(self.min_interval) {
+            interval if interval > 0 => Duration::from_secs(interval as u64),
+            _ => DEFAULT_INTERVAL,
+        }
+    }
+
+    async fn request(&mut self, announce: &Announce<'_>) -> Result<Response, Error> {
+        // A tracker id received earlier is sent back on every following announce
+        let announce = Announce {
+            tracker_id: self.tracker_id.as_deref(),
+            ..*announce
+        };
+
+        let mut response = match &mut self.protocol {
+            Protocol::Http(client) => http::announce(client, &self.announce, &announce).await?,
+            Protocol::Udp(client) => client.announce(&announce).await?,
+        };
+
+        // Peers whose host name doesn't resolve are left out
+        for host in std::mem::take(&mut response.peers.hosts) {
+            if let Ok(mut addrs) = tokio::net::lookup_host((host.ip.as_str(), host.port)).await
+                && let Some(addr) = addrs.next()
+            {
+                response.peers.addrs.push(host.to_peer(addr.ip()));
+            }
+        }
+
+        if !response.tracker_id.is_empty() {
+            self.tracker_id = Some(response.tracker_id.clone());
+        }
+
+        self.interval = response.interval;
+        self.min_interval = response.min_interval;
+
+        Ok(response)
+    }
+
+    pub async fn scrape(
+        &mut self,
+        info_hashes: &[&[u8]],
+    ) -> Result<BTreeMap<Vec<u8>, Scrape>, Error> {
+        match &mut self.protocol {
+            Protocol::Http(client) => {
+                let url = http::scrape_url(&self.announce).ok_or_else(|| {
+                    Error::InvalidUrl(String::from("Tracker does not support scrape"))
+                })?;
+                http::scrape(client, &url, info_hashes).await
+            }
+            Protocol::Udp(client) => client.scrape(info_hashes).await,
+        }
+    }
+}
+
+// Trackers from `announce-list` (BEP 12), tried tier by tier. A tracker that
+// responds is moved to the front of its tier so it is tried first next time.
+pub struct Trackers {
+    tiers: Vec<Vec<Tracker>>,
+}
+
+impl Trackers {
+    pub fn new(announce: &str, announce_list: &[Vec<String>]) -> Self {
+        let mut tiers: Vec<Vec<Tracker>> = announce_list
+            .iter()
+            .map(|tier| {
+                let mut tier: Vec<Tracker> = tier.iter().cloned().map(Tracker::new).collect();
+                tier.shuffle(&mut rand::rng());
+                tier
+            })
+            .filter(|tier| !tier.is_empty())
+            .collect();
+
+        // Clients that support announce-list ignore announce if it is present
+        if tiers.is_empty() && !announce.is_empty() {
+            tiers.push(vec![Tracker::new(announce.to_string())]);
+        }
+
+        Self { tiers }
+    }
+
+    // Announces to the first tracker that responds and adds the peers it gave to the
+    // download. The download is only locked around the request so the peer sessions
+    // are not blocked by slow trackers.
+    async fn request(
+        &mut self,
+        download: &Mutex<Download>,
+        event: Event,
+    ) -> Result<Announcement, Error> {
+        let (downloaded, info_hash, left, peer_id, uploaded) = {
+            let download = download.lock().await;
+            (
+                download.downloaded,
+                download.info_hash.clone(),
+                download.left(),
+                download.peer_id.clone(),
+                download.uploaded,
+            )
+        };
+
+        let announcement = self
+            .announce(&Announce {
+                downloaded,
+                event,
+                info_hash: &info_hash,
+                left,
+                peer_id: &peer_id,
+                port: crate::PORT,
+                tracker_id: None,
+                uploaded,
+            })
+            .await?;
+
+        download
+            .lock()
+            .await
+            .add_peers(announcement.peers.iter().cloned());
+
+        Ok(announcement)
+    }
+
+    // Asks for peers before the size of the torrent is known, e.g. while fetching
+    // the metadata of a magnet link
+    pub async fn peers(&mut self, info_hash: &[u8], peer_id: &[u8]) -> Result<Announcement, Error> {
+        self.announce(&Announce {
+            downloaded: 0,
+            event: Event::Regular,
+            info_hash,
+            // Anything but 0, which would make us look like a seeder
+            left: UNKNOWN_LEFT,
+            peer_id,
+            port: crate::PORT,
+            tracker_id: None,
+            uploaded: 0,
+        })
+        .await
+    }
+
+    async fn announce(&mut self, announce: &Announce<'_>) -> Result<Announcement, Error> {
+        let mut errors = Vec::new();
+
+        for tier in 0..self.tiers.len() {
+            for index in 0..self.tiers[tier].len() {
+                let tracker = &mut self.tiers[tier][index];
+
+                let result = tokio::time::timeout(TRACKER_TIMEOUT, tracker.request(announce))
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Timeout));
+
+                match result {
+                    Ok(response) => {
+                        let announcement = Announcement {
+                            interval: tracker.interval(),
+                            peers: response
+                                .peers
+                                .addrs
+                                .into_iter()
+                                .chain(response.peers6)
+                                .collect(),
+                            tracker: tracker.announce.clone(),
+                            warning: Some(response.warning_message)
+                                .filter(|warning| !warning.is_empty()),
+                        };
+                        self.promote(tier, index);
+
+                        return Ok(announcement);
+                    }
+                    Err(err) => errors.push((tracker.announce.clone(), err)),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Err(Error::NoTrackers)
+        } else {
+            Err(Error::Failed(errors))
+        }
+    }
+
+    // Scrapes the first tracker that responds, in the same order as announces
+    pub async fn scrape(&mut self, info_hash: &[u8]) -> Result<(&str, Scrape), Error> {
+        let mut errors = Vec::new();
+
+        for tier in 0..self.tiers.len() {
+            for index in 0..self.tiers[tier].len() {
+                let tracker = &mut self.tiers[tier][index];
+
+                let result = tokio::time::timeout(TRACKER_TIMEOUT, tracker.scrape(&[info_hash]))
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Timeout));
+
+                match result {
+                    Ok(files) => {
+                        let scrape = files.get(info_hash).copied().unwrap_or_default();
+                        self.promote(tier, index);
+                        return Ok((&self.tiers[tier][0].announce, scrape));
+                    }
+                    Err(err) => errors.push((tracker.announce.clone(), err)),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Err(Error::NoTrackers)
+        } else {
+            Err(Error::Failed(errors))
+        }
+    }
+
+    fn promote(&mut self, tier: usize, index: usize) {
+        let tracker = self.tiers[tier].remove(index);
+        self.tiers[tier].insert(0, tracker);
+    }
+
+    // Announces `started`, then re-announces on the tracker's schedule until `stop`
+    // fires, sending `completed` as soon as the last piece is verified and `stopped`
+    // on the way out. Tracker warnings go to `warnings` along with the tracker's URL.
+    pub async fn run(
+        mut self,
+        download: Arc<Mutex<Download>>,
+        mut stop: oneshot::Receiver<()>,
+        warnings: mpsc::UnboundedSender<(String, String)>,
+    ) {
+        let (mut haves, mut complete) = {
+            let download = download.lock().await;
+            (download.haves.subscribe(), download.pieces.is_complete())
+        };
+        let mut completed = false;
+        let mut started = false;
+
+        loop {
+            let event = if !started {
+                Event::Started
+            } else if completed {
+                Event::Completed
+            } else {
+                Event::Regular
+            };
+
+            // A tracker that doesn't answer must not hold up shutting down
+            let result = tokio::select! {
+                result = self.request(&download, event) => Some(result),
+                _ = &mut stop => None,
+            };
+            let Some(result) = result else {
+                self.stop(&download, started).await;
+                return;
+            };
+
+            let deadline = match result {
+                Ok(announcement) => {
+                    started = true;
+                    if event == Event::Completed {
+                        completed = false;
+                    }
+                    if let Some(warning) = announcement.warning {
+                        let _ = warnings.send((announcement.tracker, warning));
+                    }
+                    Instant::now() + announcement.interval
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    Instant::now() + RETRY_INTERVAL
+                }
+            };
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = haves.recv(), if !complete => {
+                        if download.lock().await.pieces.is_complete() {
+                            complete = true;
+                            completed = true;
+                            if started {
+                                break;
+                            }
+                        }
+                    }
+                    _ = &mut stop => {
+                        self.stop(&download, started).await;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Sends `stopped` if `started` was announced, giving up after a short while
+    async fn stop(&mut self, download: &Mutex<Download>, started: bool) {
+        if started
+            && let Err(err) =
+                tokio::time::timeout(STOPPED_TIMEOUT, self.request(download, Event::Stopped))
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Timeout))
+        {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Announce, DEFAULT_INTERVAL, Error, Event, Response, Tracker, Trackers};
+    use crate::bencode::from_bytes;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn announces(trackers: &Trackers) -> Vec<Vec<&str>> {
+        trackers
+            .tiers
+            .iter()
+            .map(|tier| {
+                tier.iter()
+                    .map(|tracker| tracker.announce.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_announce() {
+        let trackers = Trackers::new("http://a/announce", &[]);
+
+        assert_eq!(announces(&trackers), [["http://a/announce"]]);
+    }
+
+    #[test]
+    fn test_announce_list() {
+        let list = vec![
+            vec![String::from("http://b"), String::from("udp://c:80")],
+            vec![],
+            vec![String::from("http://d")],
+        ];
+        let trackers = Trackers::new("http://a", &list);
+        let mut tiers = announces(&trackers);
+        tiers[0].sort();
+
+        assert_eq!(tiers, [vec!["http://b", "udp://c:80"], vec!["http://d"]]);
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut tracker = Tracker::new(String::from("http://a"));
+
+        assert_eq!(tracker.interval(), DEFAULT_INTERVAL);
+
+        tracker.interval = 60;
+        tracker.min_interval = 300;
+
+        assert_eq!(tracker.interval(), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_min_interval() {
+        let input = b"d8:intervali1800e12:min intervali900e5:peers0:e";
+        let response = from_bytes::<Response>(input).unwrap();
+
+        assert_eq!(response.min_interval, 900);
+    }
+
+    #[test]
+    fn test_promote() {
+        let list = vec![vec![
+            String::from("http://a"),
+            String::from("http://b"),
+            String::from("http://c"),
+        ]];
+        let mut trackers = Trackers::new("", &list);
+        let last = trackers.tiers[0][2].announce.clone();
+
+        trackers.promote(0, 2);
+
+        assert_eq!(trackers.tiers[0][0].announce, last);
+        assert_eq!(trackers.tiers[0].len(), 3);
+    }
+
+    #[test]
+    fn test_compact_peers() {
+        let input = b"d8:intervali1800e5:peers12:\x7f\0\0\x01\x1a\xe1\x0a\0\0\x02\0\x50e";
+        let response = from_bytes::<Response>(input).unwrap();
+
+        assert_eq!(response.peers.addrs.len(), 2);
+        assert_eq!(response.peers.addrs[0].addr.to_string(), "127.0.0.1:6881");
+        assert_eq!(response.peers.addrs[1].addr.to_string(), "10.0.0.2:80");
+        assert!(response.peers6.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_compact_peers() {
+        let input = b"d8:intervali1800e5:peers5:\x7f\0\0\x01\x1ae";
+
+        assert!(from_bytes::<Response>(input).is_err());
+    }
+
+    #[test]
+    fn test_dictionary_peers() {
+        let input = b"d8:intervali1800e5:peersld2:ip9:127.0.0.17:peer id20:abcdefghijklmnopqrst4:porti6881eed2:ip3:::14:porti80eed2:ip11:example.com4:porti80eeee";
+        let response = from_bytes::<Response>(input).unwrap();
+
+        assert_eq!(response.peers.addrs.len(), 2);
+        assert_eq!(response.peers.addrs[0].addr.to_string(), "127.0.0.1:6881");
+        assert_eq!(
+            response.peers.addrs[0].peer_id.as_deref(),
+            Some(&b"abcdefghijklmnopqrst"[..])
+        );
+        assert_eq!(response.peers.addrs[1].addr.to_string(), "[::1]:80");
+        assert_eq!(response.peers.addrs[1].peer_id, None);
+        assert_eq!(response.peers.hosts[0].ip, "example.com");
+    }
+
+    #[test]
+    fn test_peers6() {
+        let input =
+            b"d8:intervali1800e5:peers0:6:peers618:\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\x1a\xe1e";
+        let response = from_bytes::<Response>(input).unwrap();
+
+        assert!(response.peers.addrs.is_empty());
+        assert_eq!(response.peers6[0].addr.to_string(), "[::1]:6881");
+    }
+
+    #[tokio::test]
+    async fn test_tracker_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 1024];
+                let len = stream.read(&mut buffer).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buffer[..len]).into_owned());
+
+                let body = b"d8:intervali1800e5:peers0:10:tracker id2:ide";
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(body).await.unwrap();
+            }
+
+            requests
+        });
+
+        let mut tracker = Tracker::new(url);
+        let announce = Announce {
+            downloaded: 0,
+            event: Event::Started,
+            info_hash: &[1; 20],
+            left: 100,
+            peer_id: &[2; 20],
+            port: 6881,
+            tracker_id: None,
+            uploaded: 0,
+        };
+
+        tracker.request(&announce).await.unwrap();
+        tracker.request(&announce).await.unwrap();
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("trackerid"));
+        assert!(requests[1].contains("&trackerid=%69%64 "));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let mut trackers = Trackers::new("", &[]);
+        assert!(matches!(
+            trackers.peers(&[1; 20], &[2; 20]).await,
+            Err(Error::NoTrackers)
+        ));
+
+        let list = vec![vec![String::from("udp://a")], vec![String::from("udp://b")]];
+        let mut trackers = Trackers::new("", &list);
+
+        let Err(Error::Failed(errors)) = trackers.peers(&[1; 20], &[2; 20]).await else {
+            panic!("expected every tracker to fail");
+        };
+        assert_eq!(errors[0].0, "udp://a");
+        assert_eq!(errors[1].0, "udp://b");
+        assert!(matches!(errors[1].1, Error::InvalidUrl(_)));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_hosts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 1024];
+            let _ = stream.read(&mut buffer).await.unwrap();
+
+            let body =
+                b"d8:intervali1800e5:peersld2:ip9:localhost4:porti80eed2:ip7:invalid4:porti80eeee";
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+        });
+
+        let mut tracker = Tracker::new(url);
+        let announce = Announce {
+            downloaded: 0,
+            event: Event::Started,
+            info_hash: &[1; 20],
+            left: 100,
+            peer_id: &[2; 20],
+            port: 6881,
+            tracker_id: None,
+            uploaded: 0,
+        };
+
+        let response = tracker.request(&announce).await.unwrap();
+
+        assert_eq!(response.peers.addrs.len(), 1);
+        assert!(response.peers.addrs[0].addr.ip().is_loopback());
+        assert_eq!(response.peers.addrs[0].addr.port(), 80);
+    }
+
+    #[tokio::test]
+    async fn test_warning() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/announce", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 1024];
+            let _ = stream.read(&mut buffer).await.unwrap();
+
+            let body = b"d8:intervali900e5:peers6:\x7f\0\0\x01\x1a\xe115:warning message4:slowe";
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+        });
+
+        let mut trackers = Trackers::new(&url, &[]);
+        let announcement = trackers.peers(&[1; 20], &[2; 20]).await.unwrap();
+
+        assert_eq!(announcement.interval, Duration::from_secs(900));
+        assert_eq!(announcement.peers.len(), 1);
+        assert_eq!(announcement.tracker, url);
+        assert_eq!(announcement.warning.as_deref(), Some("slow"));
+    }
+}
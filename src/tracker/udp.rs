@@ -0,0 +1,517 @@
+use crate::tracker::{
+    Announce, COMPACT_V4_LENGTH, COMPACT_V6_LENGTH, Error, Event, Peer, Peers, Response, Scrape,
+};
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ERROR: u32 = 3;
+const ACTION_SCRAPE: u32 = 2;
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+const MAX_PACKET_LENGTH: usize = 2048;
+const MAX_RETRANSMISSIONS: u32 = 8;
+// Keeps a scrape request within a single packet
+const MAX_SCRAPE_HASHES: usize = 74;
+const PROTOCOL_ID: u64 = 0x41727101980;
+const RETRANSMISSION_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct Client {
+    connection: Option<(u64, Instant)>,
+    key: u32,
+    socket: Option<UdpSocket>,
+    timeout: Duration,
+    url: String,
+}
+
+impl Client {
+    pub fn new(url: &str) -> Self {
+        Self {
+            connection: None,
+            key: rand::random(),
+            socket: None,
+            timeout: RETRANSMISSION_TIMEOUT,
+            url: url.to_string(),
+        }
+    }
+
+    pub async fn announce(&mut self, announce: &Announce<'_>) -> Result<Response, Error> {
+        let key = self.key;
+
+        let body = self
+            .request(ACTION_ANNOUNCE, |connection_id, transaction_id| {
+                announce_request(connection_id, transaction_id, key, announce)
+            })
+            .await?;
+
+        let ipv6 = self.socket()?.peer_addr()?.is_ipv6();
+
+        announce_response(&body, ipv6)
+    }
+
+    pub async fn scrape(
+        &mut self,
+        info_hashes: &[&[u8]],
+    ) -> Result<BTreeMap<Vec<u8>, Scrape>, Error> {
+        let mut files = BTreeMap::new();
+
+        for info_hashes in info_hashes.chunks(MAX_SCRAPE_HASHES) {
+            let body = self
+                .request(ACTION_SCRAPE, |connection_id, transaction_id| {
+                    scrape_request(connection_id, transaction_id, info_hashes)
+                })
+                .await?;
+
+            files.extend(scrape_response(&body, info_hashes)?);
+        }
+
+        Ok(files)
+    }
+
+    fn socket(&self) -> io::Result<&UdpSocket> {
+        self.socket
+            .as_ref()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+
+    async fn connect(&mut self) -> Result<(), Error> {
+        if self.socket.is_some() {
+            return Ok(());
+        }
+
+        let url = Url::parse(&self.url).map_err(|err| Error::InvalidUrl(err.to_string()))?;
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| Error::InvalidUrl(String::from("Missing tracker host")))?;
+        let port = url
+            .port()
+            .ok_or_else(|| Error::InvalidUrl(String::from("Missing tracker port")))?;
+
+        let addr = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+            .await?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "Unable to resolve tracker host")
+            })?;
+
+        let socket = if addr.is_ipv6() {
+            UdpSocket::bind("[::]:0").await?
+        } else {
+            UdpSocket::bind("0.0.0.0:0").await?
+        };
+        socket.connect(addr).await?;
+
+        self.socket = Some(socket);
+
+        Ok(())
+    }
+
+    // Sends a request built by `build` from a connection and a transaction id and
+    // returns the body of the matching response. A connection id is obtained first
+    // if there is no valid one. Every packet is retransmitted after 15 * 2^n seconds,
+    // where n counts all attempts of this request including connects.
+    async fn request<F>(&mut self, action: u32, build: F) -> Result<Vec<u8>, Error>
+    where
+        F: Fn(u64, u32) -> Vec<u8>,
+    {
+        self.connect().await?;
+
+        for n in 0..=MAX_RETRANSMISSIONS {
+            let timeout = self.timeout * 2u32.pow(n);
+
+            let connection_id = match self.connection {
+                Some((connection_id, obtained)) if obtained.elapsed() < CONNECTION_ID_LIFETIME => {
+                    connection_id
+                }
+                _ => {
+                    let transaction_id = rand::random();
+                    self.socket()?
+                        .send(&connect_request(transaction_id))
+                        .await?;
+
+                    match self
+                        .receive(transaction_id, ACTION_CONNECT, timeout)
+                        .await?
+                    {
+                        Some(body) if body.len() >= 8 => {
+                            let connection_id = read_u64(&body, 0);
+                            self.connection = Some((connection_id, Instant::now()));
+                            connection_id
+                        }
+                        Some(_) => return Err(malformed("Invalid connect response")),
+                        None => continue,
+                    }
+                }
+            };
+
+            let transaction_id = rand::random();
+            self.socket()?
+                .send(&build(connection_id, transaction_id))
+                .await?;
+
+            if let Some(body) = self.receive(transaction_id, action, timeout).await? {
+                return Ok(body);
+            }
+        }
+
+        Err(Error::Timeout)
+    }
+
+    // Waits for the response to `transaction_id`, returning None on timeout
+    async fn receive(
+        &self,
+        transaction_id: u32,
+        action: u32,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0u8; MAX_PACKET_LENGTH];
+
+        loop {
+            let len =
+                match tokio::time::timeout_at(deadline, self.socket()?.recv(&mut buffer)).await {
+                    Ok(len) => len?,
+                    Err(_) => return Ok(None),
+                };
+
+            let packet = &buffer[..len];
+
+            if len < 8 || read_u32(packet, 4) != transaction_id {
+                continue;
+            }
+
+            return match read_u32(packet, 0) {
+                ACTION_ERROR => Err(Error::Failure(
+                    String::from_utf8_lossy(&packet[8..]).into_owned(),
+                )),
+                response if response == action => Ok(Some(packet[8..].to_vec())),
+                _ => Err(malformed("Unexpected tracker action")),
+            };
+        }
+    }
+}
+
+fn announce_request(
+    connection_id: u64,
+    transaction_id: u32,
+    key: u32,
+    announce: &Announce,
+) -> Vec<u8> {
+    let event: u32 = match announce.event {
+        Event::Regular => 0,
+        Event::Completed => 1,
+        Event::Started => 2,
+        Event::Stopped => 3,
+    };
+
+    let mut packet = Vec::with_capacity(98);
+    packet.extend_from_slice(&connection_id.to_be_bytes());
+    packet.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet.extend_from_slice(announce.info_hash);
+    packet.extend_from_slice(announce.peer_id);
+    packet.extend_from_slice(&announce.downloaded.to_be_bytes());
+    packet.extend_from_slice(&announce.left.to_be_bytes());
+    packet.extend_from_slice(&announce.uploaded.to_be_bytes());
+    packet.extend_from_slice(&event.to_be_bytes());
+    packet.extend_from_slice(&0u32.to_be_bytes());
+    packet.extend_from_slice(&key.to_be_bytes());
+    packet.extend_from_slice(&(-1i32).to_be_bytes());
+    packet.extend_from_slice(&announce.port.to_be_bytes());
+    packet
+}
+
+// Peers are IPv6 addresses if the tracker was contacted over IPv6
+fn announce_response(body: &[u8], ipv6: bool) -> Result<Response, Error> {
+    if body.len() < 12 {
+        return Err(malformed("Invalid announce response"));
+    }
+
+    let size = if ipv6 {
+        COMPACT_V6_LENGTH
+    } else {
+        COMPACT_V4_LENGTH
+    };
+
+    Ok(Response {
+        interval: read_u32(body, 0) as i64,
+        peers: Peers {
+            addrs: body[12..]
+                .chunks_exact(size)
+                .filter_map(Peer::from_compact)
+                .collect(),
+            hosts: Vec::new(),
+        },
+        ..Response::default()
+    })
+}
+
+fn connect_request(transaction_id: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16);
+    packet.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    packet.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    packet
+}
+
+fn scrape_request(connection_id: u64, transaction_id: u32, info_hashes: &[&[u8]]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(16 + 20 * info_hashes.len());
+    packet.extend_from_slice(&connection_id.to_be_bytes());
+    packet.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+    packet.extend_from_slice(&transaction_id.to_be_bytes());
+    for info_hash in info_hashes {
+        packet.extend_from_slice(info_hash);
+    }
+    packet
+}
+
+// Counts come back as seeders, completed, leechers in the order of the request
+fn scrape_response(body: &[u8], info_hashes: &[&[u8]]) -> Result<Vec<(Vec<u8>, Scrape)>, Error> {
+    if body.len() < 12 * info_hashes.len() {
+        return Err(malformed("Invalid scrape response"));
+    }
+
+    Ok(info_hashes
+        .iter()
+        .zip(body.chunks_exact(12))
+        .map(|(info_hash, counts)| {
+            let scrape = Scrape {
+                complete: read_u32(counts, 0) as i64,
+                downloaded: read_u32(counts, 4) as i64,
+                incomplete: read_u32(counts, 8) as i64,
+            };
+            (info_hash.to_vec(), scrape)
+        })
+        .collect())
+}
+
+fn malformed(message: &str) -> Error {
+    Error::Malformed(message.to_string())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ACTION_ANNOUNCE, ACTION_CONNECT, ACTION_ERROR, ACTION_SCRAPE, CONNECTION_ID_LIFETIME,
+        Client, MAX_SCRAPE_HASHES, PROTOCOL_ID, read_u32, read_u64,
+    };
+    use crate::tracker::{Announce, Error, Event};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::time::Instant;
+
+    const CONNECTION_ID: u64 = 0x1122334455667788;
+
+    #[derive(Default)]
+    struct Mock {
+        announces: AtomicUsize,
+        connects: AtomicUsize,
+        // Number of packets to ignore before answering, to exercise retransmission
+        drop: AtomicUsize,
+        error: bool,
+        scrapes: AtomicUsize,
+    }
+
+    async fn tracker(mock: Mock) -> (String, Arc<Mock>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+        let mock = Arc::new(mock);
+        let state = mock.clone();
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 2048];
+
+            loop {
+                let (len, addr) = socket.recv_from(&mut buffer).await.unwrap();
+                let packet = &buffer[..len];
+
+                if state
+                    .drop
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok()
+                {
+                    continue;
+                }
+
+                let action = read_u32(packet, 8);
+                let mut response = Vec::new();
+
+                if state.error {
+                    response.extend_from_slice(&ACTION_ERROR.to_be_bytes());
+                    response.extend_from_slice(&packet[12..16]);
+                    response.extend_from_slice(b"torrent not registered");
+                } else if action == ACTION_CONNECT {
+                    assert_eq!(read_u64(packet, 0), PROTOCOL_ID);
+                    state.connects.fetch_add(1, Ordering::SeqCst);
+
+                    response.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                    response.extend_from_slice(&packet[12..16]);
+                    response.extend_from_slice(&CONNECTION_ID.to_be_bytes());
+                } else if action == ACTION_ANNOUNCE {
+                    assert_eq!(len, 98);
+                    assert_eq!(read_u64(packet, 0), CONNECTION_ID);
+                    assert_eq!(&packet[16..36], &[1; 20]);
+                    assert_eq!(read_u32(packet, 80), 2);
+                    assert_eq!(&packet[96..98], &6881u16.to_be_bytes());
+                    state.announces.fetch_add(1, Ordering::SeqCst);
+
+                    response.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+                    response.extend_from_slice(&packet[12..16]);
+                    response.extend_from_slice(&1800u32.to_be_bytes());
+                    response.extend_from_slice(&3u32.to_be_bytes());
+                    response.extend_from_slice(&5u32.to_be_bytes());
+                    response.extend_from_slice(&[127, 0, 0, 1, 0x1a, 0xe1]);
+                    response.extend_from_slice(&[10, 0, 0, 2, 0, 80]);
+                } else if action == ACTION_SCRAPE {
+                    assert_eq!(read_u64(packet, 0), CONNECTION_ID);
+                    state.scrapes.fetch_add(1, Ordering::SeqCst);
+
+                    response.extend_from_slice(&ACTION_SCRAPE.to_be_bytes());
+                    response.extend_from_slice(&packet[12..16]);
+                    for info_hash in packet[16..].chunks(20) {
+                        let n = info_hash[0] as u32;
+                        response.extend_from_slice(&n.to_be_bytes());
+                        response.extend_from_slice(&(n * 10).to_be_bytes());
+                        response.extend_from_slice(&(n * 2).to_be_bytes());
+                    }
+                }
+
+                socket.send_to(&response, addr).await.unwrap();
+            }
+        });
+
+        (url, mock)
+    }
+
+    fn announce() -> Announce<'static> {
+        Announce {
+            downloaded: 0,
+            event: Event::Started,
+            info_hash: &[1; 20],
+            left: 100,
+            peer_id: &[2; 20],
+            port: 6881,
+            tracker_id: None,
+            uploaded: 0,
+        }
+    }
+
+    fn client(url: &str) -> Client {
+        let mut client = Client::new(url);
+        client.timeout = Duration::from_millis(50);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_announce() {
+        let (url, mock) = tracker(Mock::default()).await;
+        let mut client = client(&url);
+
+        let response = client.announce(&announce()).await.unwrap();
+
+        assert_eq!(response.interval, 1800);
+        assert_eq!(response.peers.addrs.len(), 2);
+        assert_eq!(response.peers.addrs[0].addr.to_string(), "127.0.0.1:6881");
+        assert_eq!(response.peers.addrs[1].addr.to_string(), "10.0.0.2:80");
+        assert_eq!(mock.connects.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.announces.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_id_reuse_and_expiry() {
+        let (url, mock) = tracker(Mock::default()).await;
+        let mut client = client(&url);
+
+        client.announce(&announce()).await.unwrap();
+        client.announce(&announce()).await.unwrap();
+
+        assert_eq!(mock.connects.load(Ordering::SeqCst), 1);
+
+        let expired = Instant::now() - CONNECTION_ID_LIFETIME;
+        client.connection = client.connection.map(|(id, _)| (id, expired));
+        client.announce(&announce()).await.unwrap();
+
+        assert_eq!(mock.connects.load(Ordering::SeqCst), 2);
+        assert_eq!(mock.announces.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retransmission() {
+        let (url, mock) = tracker(Mock {
+            drop: AtomicUsize::new(2),
+            ..Mock::default()
+        })
+        .await;
+        let mut client = client(&url);
+
+        let start = Instant::now();
+        client.announce(&announce()).await.unwrap();
+
+        // Two lost packets wait for 50 ms and then 100 ms
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(mock.connects.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (url, _) = tracker(Mock {
+            drop: AtomicUsize::new(usize::MAX),
+            ..Mock::default()
+        })
+        .await;
+        let mut client = client(&url);
+        client.timeout = Duration::from_millis(1);
+
+        let err = client.announce(&announce()).await.unwrap_err();
+
+        assert!(matches!(err, Error::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_error() {
+        let (url, _) = tracker(Mock {
+            error: true,
+            ..Mock::default()
+        })
+        .await;
+        let mut client = client(&url);
+
+        let err = client.announce(&announce()).await.unwrap_err();
+
+        assert!(matches!(err, Error::Failure(reason) if reason == "torrent not registered"));
+    }
+
+    #[tokio::test]
+    async fn test_scrape() {
+        let (url, mock) = tracker(Mock::default()).await;
+        let mut client = client(&url);
+
+        let hashes: Vec<[u8; 20]> = (1..=MAX_SCRAPE_HASHES as u8 + 1).map(|n| [n; 20]).collect();
+        let info_hashes: Vec<&[u8]> = hashes.iter().map(|hash| &hash[..]).collect();
+
+        let files = client.scrape(&info_hashes).await.unwrap();
+
+        assert_eq!(files.len(), MAX_SCRAPE_HASHES + 1);
+        let scrape = files[&[3u8; 20][..]];
+        assert_eq!(scrape.complete, 3);
+        assert_eq!(scrape.downloaded, 30);
+        assert_eq!(scrape.incomplete, 6);
+        assert_eq!(mock.scrapes.load(Ordering::SeqCst), 2);
+        assert_eq!(mock.connects.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,396 @@
+use crate::download::{self, Download};
+use crate::metainfo::Info;
+use crate::piece::{Bitfield, Block, Completion};
+use reqwest::StatusCode;
+use reqwest::header::RANGE;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const IDLE_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+const MIN_BACKOFF: Duration = Duration::from_secs(15);
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct FileUrl {
+    length: i64,
+    offset: i64,
+    url: String,
+}
+
+// HTTP web seed (BEP 19). It acts like a peer that has every piece: blocks are
+// picked the same way and a whole piece is fetched with range requests.
+#[derive(Debug)]
+pub struct WebSeed {
+    backoff: Duration,
+    client: reqwest::Client,
+    files: Vec<FileUrl>,
+    piece_count: usize,
+    piece_length: i64,
+    url: String,
+}
+
+impl WebSeed {
+    pub fn new(url: &str, info: &Info) -> Self {
+        let files = info
+            .files()
+            .into_iter()
+            .map(|file| FileUrl {
+                length: file.length,
+                offset: file.offset,
+                url: file_url(url, info, file.path),
+            })
+            .collect();
+
+        Self {
+            backoff: MIN_BACKOFF,
+            client: reqwest::Client::builder()
+                .timeout(TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            files,
+            piece_count: info.piece_count(),
+            piece_length: info.piece_length(),
+            url: url.to_string(),
+        }
+    }
+
+    // Reads `length` bytes at `offset` in the torrent's byte space, with one request
+    // for every file the range covers
+    async fn fetch(&self, offset: i64, length: i64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(length as usize);
+
+        for file in &self.files {
+            let start = offset.max(file.offset);
+            let end = (offset + length).min(file.offset + file.length);
+
+            if start >= end {
+                continue;
+            }
+
+            let response = self
+                .client
+                .get(&file.url)
+                .header(
+                    RANGE,
+                    format!("bytes={}-{}", start - file.offset, end - file.offset - 1),
+                )
+                .send()
+                .await
+                .map_err(io::Error::other)?;
+
+            if response.status() != StatusCode::PARTIAL_CONTENT {
+                return Err(io::Error::other(format!(
+                    "{}: {}",
+                    file.url,
+                    response.status()
+                )));
+            }
+
+            let body = response.bytes().await.map_err(io::Error::other)?;
+
+            if body.len() as i64 != end - start {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: Unexpected length {}", file.url, body.len()),
+                ));
+            }
+
+            data.extend_from_slice(&body);
+        }
+
+        Ok(data)
+    }
+
+    // Picks the missing blocks of one piece, they are fetched with a single range
+    fn pick(&self, download: &mut Download) -> Vec<Block> {
+        let mut available = Bitfield::new(self.piece_count);
+        (0..self.piece_count).for_each(|index| available.set(index));
+
+        let mut blocks: Vec<Block> = Vec::new();
+
+        while let Some(block) = download.pieces.pick(&available, &blocks) {
+            if blocks.last().is_some_and(|last| {
+                last.index != block.index || last.begin + last.length != block.begin
+            }) {
+                download.pieces.release(block);
+                break;
+            }
+
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
+    // Downloads pieces until the torrent is complete, waiting longer after every
+    // failed request
+    pub async fn run(mut self, download: Arc<Mutex<Download>>) {
+        loop {
+            let blocks = {
+                let mut download = download.lock().await;
+
+                if download.pieces.is_complete() {
+                    return;
+                }
+
+                self.pick(&mut download)
+            };
+
+            let (Some(&first), Some(&last)) = (blocks.first(), blocks.last()) else {
+                tokio::time::sleep(IDLE_INTERVAL).await;
+                continue;
+            };
+
+            let offset = first.index as i64 * self.piece_length + first.begin as i64;
+            let length = (last.begin + last.length - first.begin) as i64;
+
+            match self.fetch(offset, length).await {
+                Ok(data) => {
+                    self.backoff = MIN_BACKOFF;
+
+                    for block in blocks {
+                        let begin = (block.begin - first.begin) as usize;
+                        let data = &data[begin..begin + block.length as usize];
+                        let completion =
+                            download
+                                .lock()
+                                .await
+                                .pieces
+                                .receive(block.index, block.begin, data);
+
+                        match completion {
+                            Some(Completion::Verified(data)) => {
+                                match download::write_piece(&download, block.index, data).await {
+                                    Ok(()) => {
+                                        println!("{}: piece {} verified", self.url, block.index)
+                                    }
+                                    Err(err) => {
+                                        eprintln!("{}: piece {}: {}", self.url, block.index, err)
+                                    }
+                                }
+                            }
+                            Some(Completion::Failed) => {
+                                eprintln!(
+                                    "{}: piece {} failed verification",
+                                    self.url, block.index
+                                );
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", self.url, err);
+
+                    {
+                        let mut download = download.lock().await;
+                        for block in blocks {
+                            download.pieces.release(block);
+                        }
+                    }
+
+                    tokio::time::sleep(self.backoff).await;
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+// A URL ending with a slash is a directory holding the torrent as it is laid out
+// on disk, for single-file torrents it can also be the file itself
+fn file_url(url: &str, info: &Info, path: &[String]) -> String {
+    if let Info::Single { .. } = info
+        && !url.ends_with('/')
+    {
+        return url.to_string();
+    }
+
+    let mut file_url = url.to_string();
+    if !file_url.ends_with('/') {
+        file_url.push('/');
+    }
+
+    file_url.push_str(&encode_path_segment(info.name()));
+
+    for part in path {
+        file_url.push('/');
+        file_url.push_str(&encode_path_segment(part));
+    }
+
+    file_url
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebSeed, file_url};
+    use crate::download::Download;
+    use crate::metainfo::{File, Info, sha1};
+    use crate::storage::Storage;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::Mutex;
+
+    fn single(name: &str) -> Info<'static> {
+        Info::Single {
+            length: 1,
+            name: name.to_string(),
+            piece_length: 1,
+            pieces: &[],
+            private: None,
+            source: None,
+        }
+    }
+
+    fn multi() -> Info<'static> {
+        Info::Multi {
+            files: vec![
+                File {
+                    length: 1,
+                    path: vec![String::from("a b"), String::from("c")],
+                },
+                File {
+                    length: 1,
+                    path: vec![String::from("d")],
+                },
+            ],
+            name: String::from("dir"),
+            piece_length: 1,
+            pieces: &[],
+            private: None,
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_file_url() {
+        assert_eq!(
+            file_url("http://a/file.iso", &single("x.iso"), &[]),
+            "http://a/file.iso"
+        );
+        assert_eq!(
+            file_url("http://a/files/", &single("x y.iso"), &[]),
+            "http://a/files/x%20y.iso"
+        );
+
+        let info = multi();
+        let urls: Vec<String> = info
+            .files()
+            .iter()
+            .map(|file| file_url("http://a/files", &info, file.path))
+            .collect();
+
+        assert_eq!(urls, ["http://a/files/dir/a%20b/c", "http://a/files/dir/d"]);
+    }
+
+    // Serves `files` with range requests, a request for a missing file fails with a 404
+    async fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 1024];
+                let len = stream.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..len]).into_owned();
+
+                let path = request.split(' ').nth(1).unwrap();
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.split_once('-'))
+                    .map(|(start, end)| {
+                        start.parse::<usize>().unwrap()..end.parse::<usize>().unwrap() + 1
+                    });
+
+                let (status, body) = match (files.get(path), range) {
+                    (Some(file), Some(range)) => ("206 Partial Content", file[range].to_vec()),
+                    _ => ("404 Not Found", Vec::new()),
+                };
+
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let data: Vec<u8> = (0..100).collect();
+        let files = HashMap::from([
+            (String::from("/dir/a%20b/c"), data[..30].to_vec()),
+            (String::from("/dir/d"), data[30..].to_vec()),
+        ]);
+        let url = serve(files).await;
+
+        let hashes: Vec<u8> = data.chunks(16).flat_map(sha1).collect();
+        let info = Info::Multi {
+            files: vec![
+                File {
+                    length: 30,
+                    path: vec![String::from("a b"), String::from("c")],
+                },
+                File {
+                    length: 70,
+                    path: vec![String::from("d")],
+                },
+            ],
+            name: String::from("dir"),
+            piece_length: 16,
+            pieces: &hashes,
+            private: None,
+            source: None,
+        };
+
+        let root = std::env::temp_dir().join(format!("shiina-webseed-{}", std::process::id()));
+        let storage = Storage::new(&info, &root).unwrap();
+        let download = Arc::new(Mutex::new(Download::new(
+            Vec::new(),
+            vec![0; 20],
+            &info,
+            storage,
+        )));
+
+        let seed = WebSeed::new(&url, &info);
+        assert_eq!(seed.fetch(25, 10).await.unwrap(), &data[25..35]);
+
+        tokio::time::timeout(Duration::from_secs(10), seed.run(download.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(download.lock().await.left(), 0);
+        assert_eq!(fs::read(root.join("dir/d")).unwrap(), &data[30..]);
+
+        let missing = WebSeed::new(&format!("{}missing/", url), &info);
+        assert!(missing.fetch(0, 10).await.is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}